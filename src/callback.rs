@@ -0,0 +1,67 @@
+//! Typed draw callbacks and pixel shader scopes, see
+//! [`Renderer::add_draw_callback`] and [`Renderer::push_pixel_shader`].
+//!
+//! imgui only knows raw callbacks, so the command added to the draw list runs
+//! a closure that merely records its id. The renderer checks for that id
+//! after invoking a raw callback and carries out the matching command itself.
+//!
+//! [`Renderer::add_draw_callback`]: crate::Renderer::add_draw_callback
+//! [`Renderer::push_pixel_shader`]: crate::Renderer::push_pixel_shader
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::device::{Device, Rect, Viewport};
+
+/// What a callback added with
+/// [`Renderer::add_draw_callback`](crate::Renderer::add_draw_callback) is
+/// called with.
+pub struct DrawCallbackInfo<'a, D: Device> {
+    /// The device the renderer draws with.
+    pub device: &'a D,
+    /// The clip rect of the callback command in framebuffer pixels, which is
+    /// also the current scissor rect.
+    pub clip_rect: Rect,
+    /// The viewport the renderer draws into.
+    pub viewport: Viewport,
+}
+
+pub(crate) type UserCallback<D> = Box<dyn FnOnce(&DrawCallbackInfo<'_, D>)>;
+
+/// A command the renderer carries out when it reaches its marker.
+pub(crate) enum DrawCallback<D: Device> {
+    /// Calls a closure added with `add_draw_callback`.
+    User(UserCallback<D>),
+    /// Draws the following commands with a pixel shader.
+    PushPixelShader(PixelShaderScope<D>),
+    /// Returns to the pixel shader active before the last push.
+    PopPixelShader,
+}
+
+/// A pixel shader and the constants it is drawn with.
+pub(crate) struct PixelShaderScope<D: Device> {
+    pub(crate) shader: D::PixelShader,
+    pub(crate) constants: Vec<[f32; 4]>,
+}
+
+/// Shared by all renderers, so one never mistakes the callback of another
+/// for its own.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static INVOKED: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+pub(crate) fn next_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The closure handed to imgui for the callback `id`.
+pub(crate) fn marker(id: usize) -> impl FnOnce() + 'static {
+    move || INVOKED.with(|invoked| invoked.set(Some(id)))
+}
+
+/// Returns the id of the last marker invoked on this thread, clearing it.
+pub(crate) fn take_invoked() -> Option<usize> {
+    INVOKED.with(Cell::take)
+}
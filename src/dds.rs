@@ -0,0 +1,296 @@
+//! A parser for DirectDraw Surface (`.dds`) files.
+//!
+//! Only plain 2D textures are understood, cube maps, volume textures and files
+//! using the `DX10` header extension are rejected. Parsing does not touch a
+//! device, [`Renderer::create_texture_dds`] uploads the result.
+//!
+//! [`Renderer::create_texture_dds`]: crate::Renderer::create_texture_dds
+
+use std::{error, fmt};
+
+use crate::device::consts::*;
+use crate::pixels;
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const PIXEL_FORMAT_SIZE: u32 = 32;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_DEPTH: u32 = 0x800000;
+
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+
+const FOURCC_DX10: u32 = u32::from_le_bytes(*b"DX10");
+
+/// The reasons a DDS file can be rejected for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DdsError {
+    /// The data does not start with the `DDS ` magic.
+    InvalidMagic,
+    /// The header has an unexpected size or describes an impossible surface.
+    InvalidHeader,
+    /// The pixel format has no Direct3D 9 equivalent.
+    UnsupportedFormat,
+    /// The file holds a cube map or volume texture or uses the `DX10` header.
+    UnsupportedLayout,
+    /// The data ends before the last mip level does.
+    UnexpectedEof,
+}
+
+impl fmt::Display for DdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DdsError::InvalidMagic => "not a DDS file",
+            DdsError::InvalidHeader => "invalid DDS header",
+            DdsError::UnsupportedFormat => "unsupported DDS pixel format",
+            DdsError::UnsupportedLayout => "only 2D DDS textures are supported",
+            DdsError::UnexpectedEof => "DDS file is truncated",
+        })
+    }
+}
+
+impl error::Error for DdsError {}
+
+/// A parsed DDS file borrowing its pixel data.
+#[derive(Clone, Debug)]
+pub struct Dds<'a> {
+    /// The width of the top level in pixels.
+    pub width: u32,
+    /// The height of the top level in pixels.
+    pub height: u32,
+    /// The `D3DFMT_*` format the levels are stored in.
+    pub format: u32,
+    /// The mip levels, largest first.
+    pub levels: Vec<DdsLevel<'a>>,
+}
+
+/// A single mip level of a [`Dds`].
+#[derive(Copy, Clone, Debug)]
+pub struct DdsLevel<'a> {
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+    /// The size in bytes of one row of pixels, or of 4x4 blocks for the DXTn
+    /// formats.
+    pub pitch: usize,
+    /// The number of rows of pixels or blocks.
+    pub rows: usize,
+    /// The tightly packed data of the level, `pitch * rows` bytes.
+    pub data: &'a [u8],
+}
+
+impl<'a> Dds<'a> {
+    /// Parses the header of a DDS file and splits its data into mip levels.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DdsError> {
+        if bytes.get(..4) != Some(MAGIC) {
+            return Err(DdsError::InvalidMagic);
+        }
+        let header = bytes.get(4..4 + HEADER_SIZE).ok_or(DdsError::UnexpectedEof)?;
+        let field = |offset: usize| {
+            u32::from_le_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+        if field(0) != HEADER_SIZE as u32 || field(72) != PIXEL_FORMAT_SIZE {
+            return Err(DdsError::InvalidHeader);
+        }
+        let (flags, height, width) = (field(4), field(8), field(12));
+        if width == 0 || height == 0 {
+            return Err(DdsError::InvalidHeader);
+        }
+        if flags & DDSD_DEPTH != 0 || field(108) & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+            return Err(DdsError::UnsupportedLayout);
+        }
+        let format = pixel_format(
+            field(76),
+            field(80),
+            field(84),
+            [field(88), field(92), field(96), field(100)],
+        )?;
+        let level_count = if flags & DDSD_MIPMAPCOUNT != 0 { field(24).max(1) } else { 1 };
+        if level_count > 32 - width.max(height).leading_zeros() {
+            return Err(DdsError::InvalidHeader);
+        }
+
+        let mut data = &bytes[4 + HEADER_SIZE..];
+        let levels = (0..level_count)
+            .map(|level| {
+                let width = (width >> level).max(1);
+                let height = (height >> level).max(1);
+                let (pitch, rows) = pixels::surface_layout(format, width, height);
+                if data.len() < pitch * rows {
+                    return Err(DdsError::UnexpectedEof);
+                }
+                let (level_data, rest) = data.split_at(pitch * rows);
+                data = rest;
+                Ok(DdsLevel { width, height, pitch, rows, data: level_data })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Dds { width, height, format, levels })
+    }
+}
+
+/// Maps a `DDS_PIXELFORMAT` to the matching `D3DFMT_*` value.
+fn pixel_format(
+    flags: u32,
+    four_cc: u32,
+    bit_count: u32,
+    masks: [u32; 4],
+) -> Result<u32, DdsError> {
+    if flags & DDPF_FOURCC != 0 {
+        return match four_cc {
+            D3DFMT_DXT1 | D3DFMT_DXT2 | D3DFMT_DXT3 | D3DFMT_DXT4 | D3DFMT_DXT5 => Ok(four_cc),
+            FOURCC_DX10 => Err(DdsError::UnsupportedLayout),
+            _ => Err(DdsError::UnsupportedFormat),
+        };
+    }
+    let [r, g, b, a] = masks;
+    let a = if flags & (DDPF_ALPHAPIXELS | DDPF_ALPHA) != 0 { a } else { 0 };
+    let format = if flags & DDPF_RGB != 0 {
+        match (bit_count, r, g, b, a) {
+            (32, 0xFF0000, 0xFF00, 0xFF, 0xFF000000) => D3DFMT_A8R8G8B8,
+            (32, 0xFF0000, 0xFF00, 0xFF, 0) => D3DFMT_X8R8G8B8,
+            (32, 0xFF, 0xFF00, 0xFF0000, 0xFF000000) => D3DFMT_A8B8G8R8,
+            (32, 0xFF, 0xFF00, 0xFF0000, 0) => D3DFMT_X8B8G8R8,
+            (16, 0xF800, 0x7E0, 0x1F, 0) => D3DFMT_R5G6B5,
+            (16, 0x7C00, 0x3E0, 0x1F, 0x8000) => D3DFMT_A1R5G5B5,
+            (16, 0x7C00, 0x3E0, 0x1F, 0) => D3DFMT_X1R5G5B5,
+            (16, 0xF00, 0xF0, 0xF, 0xF000) => D3DFMT_A4R4G4B4,
+            _ => return Err(DdsError::UnsupportedFormat),
+        }
+    } else if flags & DDPF_LUMINANCE != 0 {
+        match (bit_count, r, a) {
+            (8, 0xFF, 0) => D3DFMT_L8,
+            (16, 0xFF, 0xFF00) => D3DFMT_A8L8,
+            _ => return Err(DdsError::UnsupportedFormat),
+        }
+    } else if flags & DDPF_ALPHA != 0 && bit_count == 8 && a == 0xFF {
+        D3DFMT_A8
+    } else {
+        return Err(DdsError::UnsupportedFormat);
+    };
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Fixture;
+    use crate::{RendererOptions, SamplerState, TextureAddress};
+
+    const DDSD_FLAGS: u32 = 0x1 | 0x2 | 0x4 | 0x1000;
+
+    /// A DDS file with the given header fields followed by `data_len` bytes
+    /// counting up from zero.
+    fn file(
+        flags: u32,
+        [width, height]: [u32; 2],
+        mip_count: u32,
+        (pf_flags, four_cc, bit_count, masks): (u32, u32, u32, [u32; 4]),
+        caps2: u32,
+        data_len: usize,
+    ) -> Vec<u8> {
+        let mut header = [0u32; HEADER_SIZE / 4];
+        header[0] = HEADER_SIZE as u32;
+        header[1] = DDSD_FLAGS | flags;
+        header[2] = height;
+        header[3] = width;
+        header[6] = mip_count;
+        header[18] = PIXEL_FORMAT_SIZE;
+        header[19] = pf_flags;
+        header[20] = four_cc;
+        header[21] = bit_count;
+        header[22..26].copy_from_slice(&masks);
+        header[27] = caps2;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(header.iter().flat_map(|field| field.to_le_bytes()));
+        bytes.extend((0..data_len).map(|i| i as u8));
+        bytes
+    }
+
+    fn four_cc(format: u32) -> (u32, u32, u32, [u32; 4]) {
+        (DDPF_FOURCC, format, 0, [0; 4])
+    }
+
+    #[test]
+    fn dxt1_mip_chain() {
+        let bytes = file(DDSD_MIPMAPCOUNT, [8, 8], 4, four_cc(D3DFMT_DXT1), 0, 16 * 2 + 8 * 3);
+        let dds = Dds::parse(&bytes).unwrap();
+        assert_eq!((dds.width, dds.height, dds.format), (8, 8, D3DFMT_DXT1));
+        let layout: Vec<_> =
+            dds.levels.iter().map(|l| (l.width, l.height, l.pitch, l.rows)).collect();
+        assert_eq!(layout, [(8, 8, 16, 2), (4, 4, 8, 1), (2, 2, 8, 1), (1, 1, 8, 1)]);
+        assert_eq!(dds.levels[1].data, &bytes[4 + HEADER_SIZE + 32..][..8]);
+    }
+
+    #[test]
+    fn dxt_sizes_round_up_to_whole_blocks() {
+        let bytes = file(0, [6, 5], 0, four_cc(D3DFMT_DXT5), 0, 2 * 16 * 2);
+        let dds = Dds::parse(&bytes).unwrap();
+        assert_eq!(dds.levels.len(), 1);
+        assert_eq!((dds.levels[0].pitch, dds.levels[0].rows), (32, 2));
+    }
+
+    #[test]
+    fn uncompressed_rgb() {
+        let masks = [0xFF0000, 0xFF00, 0xFF, 0xFF000000];
+        let pf = (DDPF_RGB | DDPF_ALPHAPIXELS, 0, 32, masks);
+        let bytes = file(0, [3, 2], 0, pf, 0, 24);
+        let dds = Dds::parse(&bytes).unwrap();
+        assert_eq!(dds.format, D3DFMT_A8R8G8B8);
+        assert_eq!((dds.levels[0].pitch, dds.levels[0].rows), (12, 2));
+    }
+
+    #[test]
+    fn truncated_files() {
+        let bytes = file(0, [8, 8], 0, four_cc(D3DFMT_DXT1), 0, 31);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::UnexpectedEof);
+        assert_eq!(Dds::parse(&bytes[..100]).unwrap_err(), DdsError::UnexpectedEof);
+        let bytes = file(DDSD_MIPMAPCOUNT, [8, 8], 2, four_cc(D3DFMT_DXT1), 0, 32 + 7);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejected_headers() {
+        let mut bytes = file(0, [4, 4], 0, four_cc(D3DFMT_DXT1), 0, 8);
+        bytes[0] = b'X';
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::InvalidMagic);
+
+        let bytes = file(0, [4, 4], 0, four_cc(D3DFMT_DXT1), DDSCAPS2_CUBEMAP, 8 * 6);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::UnsupportedLayout);
+        let bytes = file(0, [4, 4], 0, four_cc(FOURCC_DX10), 0, 8);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::UnsupportedLayout);
+
+        let pf = (DDPF_RGB, 0, 24, [0xFF0000, 0xFF00, 0xFF, 0]);
+        let bytes = file(0, [4, 4], 0, pf, 0, 48);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::UnsupportedFormat);
+
+        // A 4x4 surface has three levels at most.
+        let bytes = file(DDSD_MIPMAPCOUNT, [4, 4], 4, four_cc(D3DFMT_DXT1), 0, 8 * 4);
+        assert_eq!(Dds::parse(&bytes).unwrap_err(), DdsError::InvalidHeader);
+    }
+
+    #[test]
+    fn renderer_uploads_every_level_with_the_sampler() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let sampler = SamplerState { address_u: TextureAddress::Clamp, ..SamplerState::default() };
+        let bytes = file(DDSD_MIPMAPCOUNT, [8, 8], 4, four_cc(D3DFMT_DXT1), 0, 16 * 2 + 8 * 3);
+        let (handle, size) =
+            fixture.renderer.create_texture_dds_with_sampler(&bytes, sampler).unwrap();
+        assert_eq!(size, [8, 8]);
+        assert_eq!(fixture.renderer.texture_sampler(handle.id()), sampler);
+        let level = fixture.device.resource_data(*handle.texture(), 3).unwrap();
+        assert_eq!(level, &bytes[4 + HEADER_SIZE + 48..]);
+    }
+}
@@ -0,0 +1,241 @@
+//! The device abstraction the [`Renderer`](crate::Renderer) draws through.
+//!
+//! Every call the renderer makes against Direct3D 9 goes through the
+//! [`Device`] trait. [`IDirect3DDevice9`] implements it on Windows, while
+//! [`RecordingDevice`](crate::recording::RecordingDevice) keeps everything in
+//! memory so the renderer can be driven without a GPU.
+//!
+//! Enumerations are passed as their raw Direct3D 9 values, see [`consts`].
+//!
+//! [`IDirect3DDevice9`]: https://learn.microsoft.com/en-us/windows/win32/api/d3d9/nn-d3d9-idirect3ddevice9
+
+use std::ffi::c_void;
+
+use windows_core::Result;
+use windows_numerics::Matrix4x4;
+
+pub mod consts;
+#[cfg(windows)]
+mod d3d9;
+
+/// A rectangle in framebuffer pixels, mirroring `RECT`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// The x coordinate of the upper-left corner.
+    pub left: i32,
+    /// The y coordinate of the upper-left corner.
+    pub top: i32,
+    /// The x coordinate of the lower-right corner.
+    pub right: i32,
+    /// The y coordinate of the lower-right corner.
+    pub bottom: i32,
+}
+
+/// A viewport, mirroring `D3DVIEWPORT9`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Viewport {
+    /// The x coordinate of the upper-left corner.
+    pub x: u32,
+    /// The y coordinate of the upper-left corner.
+    pub y: u32,
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+    /// The minimum depth value.
+    pub min_z: f32,
+    /// The maximum depth value.
+    pub max_z: f32,
+}
+
+/// The description of a texture level, mirroring `D3DSURFACE_DESC`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SurfaceDesc {
+    /// The `D3DFMT_*` format of the level.
+    pub format: u32,
+    /// The `D3DUSAGE_*` flags the texture was created with.
+    pub usage: u32,
+    /// The `D3DPOOL_*` the texture lives in.
+    pub pool: u32,
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+}
+
+/// A locked texture level, mirroring `D3DLOCKED_RECT`.
+#[derive(Copy, Clone, Debug)]
+pub struct LockedRect {
+    /// The number of bytes in one row, or one row of blocks for compressed
+    /// formats.
+    pub pitch: usize,
+    /// Pointer to the first byte of the locked area.
+    pub bits: *mut c_void,
+}
+
+/// The subset of `IDirect3DDevice9` the renderer uses.
+///
+/// Method names and arguments follow their Direct3D 9 counterparts, with
+/// enumerations taken as raw values from [`consts`].
+///
+/// # Safety
+///
+/// The lock methods must return pointers that stay valid for reads and writes
+/// of the requested size until the matching unlock call.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait Device {
+    /// The texture type, `IDirect3DBaseTexture9` for the COM device.
+    type Texture: Clone;
+    /// The vertex buffer type.
+    type VertexBuffer;
+    /// The index buffer type.
+    type IndexBuffer;
+    /// The state block type.
+    type StateBlock: Clone;
+    /// The pixel shader type.
+    type PixelShader: Clone;
+    /// The vertex shader type.
+    type VertexShader;
+
+    /// `IDirect3DDevice9::TestCooperativeLevel`
+    unsafe fn test_cooperative_level(&self) -> Result<()>;
+    /// `IDirect3D9::CheckDeviceFormat` for a texture on the adapter and
+    /// display mode of this device.
+    unsafe fn check_texture_format(&self, usage: u32, format: u32) -> Result<()>;
+    /// `IDirect3D9::CheckDeviceFormat` for a surface on the adapter and
+    /// display mode of this device.
+    unsafe fn check_surface_format(&self, usage: u32, format: u32) -> Result<()>;
+    /// `IDirect3DDevice9::GetRenderTarget` followed by
+    /// `IDirect3DSurface9::GetDesc`, returning the format of the render
+    /// target.
+    unsafe fn get_render_target_format(&self, index: u32) -> Result<u32>;
+
+    /// `IDirect3DDevice9::CreateVertexBuffer`
+    unsafe fn create_vertex_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        fvf: u32,
+        pool: u32,
+    ) -> Result<Self::VertexBuffer>;
+    /// `IDirect3DDevice9::CreateIndexBuffer`
+    unsafe fn create_index_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::IndexBuffer>;
+    /// `IDirect3DDevice9::CreateTexture`
+    unsafe fn create_texture(
+        &self,
+        width: u32,
+        height: u32,
+        levels: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::Texture>;
+    /// `IDirect3DDevice9::CreateVertexShader`
+    unsafe fn create_vertex_shader(&self, function: &[u32]) -> Result<Self::VertexShader>;
+    /// `IDirect3DDevice9::CreatePixelShader`
+    unsafe fn create_pixel_shader(&self, function: &[u32]) -> Result<Self::PixelShader>;
+    /// `IDirect3DDevice9::CreateStateBlock`
+    unsafe fn create_state_block(&self, ty: u32) -> Result<Self::StateBlock>;
+    /// `IDirect3DDevice9::BeginStateBlock`
+    unsafe fn begin_state_block(&self) -> Result<()>;
+    /// `IDirect3DDevice9::EndStateBlock`
+    unsafe fn end_state_block(&self) -> Result<Self::StateBlock>;
+
+    /// `IDirect3DVertexBuffer9::Lock`
+    unsafe fn lock_vertex_buffer(
+        &self,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void>;
+    /// `IDirect3DVertexBuffer9::Unlock`
+    unsafe fn unlock_vertex_buffer(&self, buffer: &Self::VertexBuffer) -> Result<()>;
+    /// `IDirect3DIndexBuffer9::Lock`
+    unsafe fn lock_index_buffer(
+        &self,
+        buffer: &Self::IndexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void>;
+    /// `IDirect3DIndexBuffer9::Unlock`
+    unsafe fn unlock_index_buffer(&self, buffer: &Self::IndexBuffer) -> Result<()>;
+    /// `IDirect3DBaseTexture9::GetLevelCount`
+    unsafe fn get_level_count(&self, texture: &Self::Texture) -> u32;
+    /// `IDirect3DTexture9::GetLevelDesc`
+    unsafe fn get_level_desc(&self, texture: &Self::Texture, level: u32) -> Result<SurfaceDesc>;
+    /// `IDirect3DTexture9::LockRect`
+    unsafe fn lock_rect(
+        &self,
+        texture: &Self::Texture,
+        level: u32,
+        rect: Option<&Rect>,
+        flags: u32,
+    ) -> Result<LockedRect>;
+    /// `IDirect3DTexture9::UnlockRect`
+    unsafe fn unlock_rect(&self, texture: &Self::Texture, level: u32) -> Result<()>;
+    /// `IDirect3DStateBlock9::Capture`
+    unsafe fn capture_state_block(&self, block: &Self::StateBlock) -> Result<()>;
+    /// `IDirect3DStateBlock9::Apply`
+    unsafe fn apply_state_block(&self, block: &Self::StateBlock) -> Result<()>;
+
+    /// `IDirect3DDevice9::SetViewport`
+    unsafe fn set_viewport(&self, viewport: &Viewport) -> Result<()>;
+    /// `IDirect3DDevice9::SetPixelShader`
+    unsafe fn set_pixel_shader(&self, shader: Option<&Self::PixelShader>) -> Result<()>;
+    /// `IDirect3DDevice9::SetVertexShader`
+    unsafe fn set_vertex_shader(&self, shader: Option<&Self::VertexShader>) -> Result<()>;
+    /// `IDirect3DDevice9::SetVertexShaderConstantF`
+    unsafe fn set_vertex_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()>;
+    /// `IDirect3DDevice9::SetPixelShaderConstantF`
+    unsafe fn set_pixel_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()>;
+    /// `IDirect3DDevice9::SetRenderState`
+    unsafe fn set_render_state(&self, state: u32, value: u32) -> Result<()>;
+    /// `IDirect3DDevice9::SetTextureStageState`
+    unsafe fn set_texture_stage_state(&self, stage: u32, ty: u32, value: u32) -> Result<()>;
+    /// `IDirect3DDevice9::SetSamplerState`
+    unsafe fn set_sampler_state(&self, sampler: u32, ty: u32, value: u32) -> Result<()>;
+    /// `IDirect3DDevice9::SetTransform`
+    unsafe fn set_transform(&self, state: u32, matrix: &Matrix4x4) -> Result<()>;
+    /// `IDirect3DDevice9::SetTexture`
+    unsafe fn set_texture(&self, stage: u32, texture: Option<&Self::Texture>) -> Result<()>;
+    /// `IDirect3DDevice9::SetScissorRect`
+    unsafe fn set_scissor_rect(&self, rect: &Rect) -> Result<()>;
+    /// `IDirect3DDevice9::SetStreamSource`
+    unsafe fn set_stream_source(
+        &self,
+        stream: u32,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        stride: u32,
+    ) -> Result<()>;
+    /// `IDirect3DDevice9::SetIndices`
+    unsafe fn set_indices(&self, buffer: &Self::IndexBuffer) -> Result<()>;
+    /// `IDirect3DDevice9::SetFVF`
+    unsafe fn set_fvf(&self, fvf: u32) -> Result<()>;
+    /// `IDirect3DDevice9::DrawIndexedPrimitive`
+    unsafe fn draw_indexed_primitive(
+        &self,
+        primitive_type: u32,
+        base_vertex_index: i32,
+        min_vertex_index: u32,
+        num_vertices: u32,
+        start_index: u32,
+        prim_count: u32,
+    ) -> Result<()>;
+}
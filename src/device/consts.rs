@@ -0,0 +1,125 @@
+//! Raw Direct3D 9 enumeration values used by the [`Device`](super::Device)
+//! trait.
+//!
+//! Names match `d3d9types.h` so they read the same as their Windows
+//! counterparts.
+#![allow(missing_docs)]
+
+use windows_core::HRESULT;
+
+pub const D3DBLENDOP_ADD: u32 = 1;
+
+pub const D3DBLEND_ONE: u32 = 2;
+pub const D3DBLEND_SRCALPHA: u32 = 5;
+pub const D3DBLEND_INVSRCALPHA: u32 = 6;
+
+pub const D3DCULL_NONE: u32 = 1;
+
+pub const D3DFILL_SOLID: u32 = 3;
+
+pub const D3DFMT_A8R8G8B8: u32 = 21;
+pub const D3DFMT_X8R8G8B8: u32 = 22;
+pub const D3DFMT_R5G6B5: u32 = 23;
+pub const D3DFMT_X1R5G5B5: u32 = 24;
+pub const D3DFMT_A1R5G5B5: u32 = 25;
+pub const D3DFMT_A4R4G4B4: u32 = 26;
+pub const D3DFMT_A8: u32 = 28;
+pub const D3DFMT_A8B8G8R8: u32 = 32;
+pub const D3DFMT_X8B8G8R8: u32 = 33;
+pub const D3DFMT_L8: u32 = 50;
+pub const D3DFMT_A8L8: u32 = 51;
+pub const D3DFMT_DXT1: u32 = u32::from_le_bytes(*b"DXT1");
+pub const D3DFMT_DXT2: u32 = u32::from_le_bytes(*b"DXT2");
+pub const D3DFMT_DXT3: u32 = u32::from_le_bytes(*b"DXT3");
+pub const D3DFMT_DXT4: u32 = u32::from_le_bytes(*b"DXT4");
+pub const D3DFMT_DXT5: u32 = u32::from_le_bytes(*b"DXT5");
+pub const D3DFMT_INDEX16: u32 = 101;
+pub const D3DFMT_INDEX32: u32 = 102;
+
+pub const D3DFVF_XYZ: u32 = 0x002;
+pub const D3DFVF_DIFFUSE: u32 = 0x040;
+pub const D3DFVF_TEX1: u32 = 0x100;
+
+pub const D3DLOCK_READONLY: u32 = 0x10;
+pub const D3DLOCK_DISCARD: u32 = 0x2000;
+
+pub const D3DPOOL_DEFAULT: u32 = 0;
+pub const D3DPOOL_MANAGED: u32 = 1;
+
+pub const D3DPT_TRIANGLELIST: u32 = 4;
+
+pub const D3DRS_ZENABLE: u32 = 7;
+pub const D3DRS_FILLMODE: u32 = 8;
+pub const D3DRS_SHADEMODE: u32 = 9;
+pub const D3DRS_ZWRITEENABLE: u32 = 14;
+pub const D3DRS_ALPHATESTENABLE: u32 = 15;
+pub const D3DRS_SRCBLEND: u32 = 19;
+pub const D3DRS_DESTBLEND: u32 = 20;
+pub const D3DRS_CULLMODE: u32 = 22;
+pub const D3DRS_ALPHABLENDENABLE: u32 = 27;
+pub const D3DRS_FOGENABLE: u32 = 28;
+pub const D3DRS_SPECULARENABLE: u32 = 29;
+pub const D3DRS_RANGEFOGENABLE: u32 = 48;
+pub const D3DRS_STENCILENABLE: u32 = 52;
+pub const D3DRS_CLIPPING: u32 = 136;
+pub const D3DRS_LIGHTING: u32 = 137;
+pub const D3DRS_BLENDOP: u32 = 171;
+pub const D3DRS_SCISSORTESTENABLE: u32 = 174;
+pub const D3DRS_SRGBWRITEENABLE: u32 = 194;
+pub const D3DRS_SEPARATEALPHABLENDENABLE: u32 = 206;
+pub const D3DRS_SRCBLENDALPHA: u32 = 207;
+pub const D3DRS_DESTBLENDALPHA: u32 = 208;
+
+pub const D3DSAMP_ADDRESSU: u32 = 1;
+pub const D3DSAMP_ADDRESSV: u32 = 2;
+pub const D3DSAMP_BORDERCOLOR: u32 = 4;
+pub const D3DSAMP_MAGFILTER: u32 = 5;
+pub const D3DSAMP_MINFILTER: u32 = 6;
+pub const D3DSAMP_MIPFILTER: u32 = 7;
+pub const D3DSAMP_MAXANISOTROPY: u32 = 10;
+pub const D3DSAMP_SRGBTEXTURE: u32 = 11;
+
+pub const D3DSBT_ALL: u32 = 1;
+
+pub const D3DSHADE_GOURAUD: u32 = 2;
+
+pub const D3DTA_DIFFUSE: u32 = 0;
+pub const D3DTA_TEXTURE: u32 = 2;
+
+pub const D3DTADDRESS_WRAP: u32 = 1;
+pub const D3DTADDRESS_MIRROR: u32 = 2;
+pub const D3DTADDRESS_CLAMP: u32 = 3;
+pub const D3DTADDRESS_BORDER: u32 = 4;
+pub const D3DTADDRESS_MIRRORONCE: u32 = 5;
+
+pub const D3DTEXF_NONE: u32 = 0;
+pub const D3DTEXF_POINT: u32 = 1;
+pub const D3DTEXF_LINEAR: u32 = 2;
+pub const D3DTEXF_ANISOTROPIC: u32 = 3;
+
+pub const D3DTOP_DISABLE: u32 = 1;
+pub const D3DTOP_SELECTARG2: u32 = 3;
+pub const D3DTOP_MODULATE: u32 = 4;
+
+pub const D3DTS_VIEW: u32 = 2;
+pub const D3DTS_PROJECTION: u32 = 3;
+pub const D3DTS_WORLD: u32 = 256;
+
+pub const D3DTSS_COLOROP: u32 = 1;
+pub const D3DTSS_COLORARG1: u32 = 2;
+pub const D3DTSS_COLORARG2: u32 = 3;
+pub const D3DTSS_ALPHAOP: u32 = 4;
+pub const D3DTSS_ALPHAARG1: u32 = 5;
+pub const D3DTSS_ALPHAARG2: u32 = 6;
+
+pub const D3DUSAGE_WRITEONLY: u32 = 0x008;
+pub const D3DUSAGE_DYNAMIC: u32 = 0x200;
+pub const D3DUSAGE_QUERY_SRGBREAD: u32 = 0x10000;
+pub const D3DUSAGE_QUERY_SRGBWRITE: u32 = 0x40000;
+
+pub const D3DERR_DEVICELOST: HRESULT = HRESULT(0x88760868_u32 as i32);
+pub const D3DERR_DEVICENOTRESET: HRESULT = HRESULT(0x88760869_u32 as i32);
+pub const D3DERR_NOTAVAILABLE: HRESULT = HRESULT(0x8876086A_u32 as i32);
+pub const D3DERR_INVALIDCALL: HRESULT = HRESULT(0x8876086C_u32 as i32);
+pub const E_INVALIDARG: HRESULT = HRESULT(0x80070057_u32 as i32);
+pub const E_POINTER: HRESULT = HRESULT(0x80004003_u32 as i32);
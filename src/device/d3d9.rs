@@ -0,0 +1,338 @@
+use std::ffi::c_void;
+use std::ptr;
+
+use windows::core::Interface;
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Direct3D9::{
+    IDirect3DBaseTexture9, IDirect3DDevice9, IDirect3DIndexBuffer9, IDirect3DPixelShader9,
+    IDirect3DStateBlock9, IDirect3DTexture9, IDirect3DVertexBuffer9, IDirect3DVertexShader9,
+    D3DDEVICE_CREATION_PARAMETERS, D3DDISPLAYMODE, D3DFORMAT, D3DLOCKED_RECT, D3DPOOL,
+    D3DPRIMITIVETYPE, D3DRENDERSTATETYPE, D3DRESOURCETYPE, D3DRTYPE_SURFACE, D3DRTYPE_TEXTURE,
+    D3DSAMPLERSTATETYPE, D3DSTATEBLOCKTYPE, D3DSURFACE_DESC, D3DTEXTURESTAGESTATETYPE,
+    D3DTRANSFORMSTATETYPE, D3DVIEWPORT9,
+};
+use windows_core::Result;
+use windows_numerics::Matrix4x4;
+
+use super::consts::E_POINTER;
+use super::{Device, LockedRect, Rect, SurfaceDesc, Viewport};
+
+impl From<&Rect> for RECT {
+    #[inline]
+    fn from(r: &Rect) -> Self {
+        RECT { left: r.left, top: r.top, right: r.right, bottom: r.bottom }
+    }
+}
+
+/// `IDirect3D9::CheckDeviceFormat` on the adapter and display mode of
+/// `device`.
+unsafe fn check_device_format(
+    device: &IDirect3DDevice9,
+    usage: u32,
+    resource_type: D3DRESOURCETYPE,
+    format: u32,
+) -> Result<()> {
+    let mut params = D3DDEVICE_CREATION_PARAMETERS::default();
+    device.GetCreationParameters(&mut params)?;
+    let mut mode = D3DDISPLAYMODE::default();
+    device.GetDisplayMode(0, &mut mode)?;
+    device.GetDirect3D()?.CheckDeviceFormat(
+        params.AdapterOrdinal,
+        params.DeviceType,
+        mode.Format,
+        usage,
+        resource_type,
+        D3DFORMAT(format),
+    )
+}
+
+unsafe impl Device for IDirect3DDevice9 {
+    type Texture = IDirect3DBaseTexture9;
+    type VertexBuffer = IDirect3DVertexBuffer9;
+    type IndexBuffer = IDirect3DIndexBuffer9;
+    type StateBlock = IDirect3DStateBlock9;
+    type PixelShader = IDirect3DPixelShader9;
+    type VertexShader = IDirect3DVertexShader9;
+
+    unsafe fn test_cooperative_level(&self) -> Result<()> {
+        self.TestCooperativeLevel()
+    }
+
+    unsafe fn check_texture_format(&self, usage: u32, format: u32) -> Result<()> {
+        check_device_format(self, usage, D3DRTYPE_TEXTURE, format)
+    }
+
+    unsafe fn check_surface_format(&self, usage: u32, format: u32) -> Result<()> {
+        check_device_format(self, usage, D3DRTYPE_SURFACE, format)
+    }
+
+    unsafe fn get_render_target_format(&self, index: u32) -> Result<u32> {
+        let mut desc = D3DSURFACE_DESC::default();
+        self.GetRenderTarget(index)?.GetDesc(&mut desc)?;
+        Ok(desc.Format.0)
+    }
+
+    unsafe fn create_vertex_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        fvf: u32,
+        pool: u32,
+    ) -> Result<Self::VertexBuffer> {
+        let mut vertex_buffer: Option<IDirect3DVertexBuffer9> = None;
+        self.CreateVertexBuffer(
+            length,
+            usage,
+            fvf,
+            D3DPOOL(pool as i32),
+            &mut vertex_buffer,
+            ptr::null_mut(),
+        )?;
+        Ok(vertex_buffer.ok_or(E_POINTER)?)
+    }
+
+    unsafe fn create_index_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::IndexBuffer> {
+        let mut index_buffer: Option<IDirect3DIndexBuffer9> = None;
+        self.CreateIndexBuffer(
+            length,
+            usage,
+            D3DFORMAT(format),
+            D3DPOOL(pool as i32),
+            &mut index_buffer,
+            ptr::null_mut(),
+        )?;
+        Ok(index_buffer.ok_or(E_POINTER)?)
+    }
+
+    unsafe fn create_texture(
+        &self,
+        width: u32,
+        height: u32,
+        levels: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::Texture> {
+        let mut texture: Option<IDirect3DTexture9> = None;
+        self.CreateTexture(
+            width,
+            height,
+            levels,
+            usage,
+            D3DFORMAT(format),
+            D3DPOOL(pool as i32),
+            &mut texture,
+            ptr::null_mut(),
+        )?;
+        Ok(texture.ok_or(E_POINTER)?.into())
+    }
+
+    unsafe fn create_vertex_shader(&self, function: &[u32]) -> Result<Self::VertexShader> {
+        self.CreateVertexShader(function.as_ptr())
+    }
+
+    unsafe fn create_pixel_shader(&self, function: &[u32]) -> Result<Self::PixelShader> {
+        self.CreatePixelShader(function.as_ptr())
+    }
+
+    unsafe fn create_state_block(&self, ty: u32) -> Result<Self::StateBlock> {
+        self.CreateStateBlock(D3DSTATEBLOCKTYPE(ty as i32))
+    }
+
+    unsafe fn begin_state_block(&self) -> Result<()> {
+        self.BeginStateBlock()
+    }
+
+    unsafe fn end_state_block(&self) -> Result<Self::StateBlock> {
+        self.EndStateBlock()
+    }
+
+    unsafe fn lock_vertex_buffer(
+        &self,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void> {
+        let mut data = ptr::null_mut();
+        buffer.Lock(offset, size, &mut data, flags)?;
+        Ok(data)
+    }
+
+    unsafe fn unlock_vertex_buffer(&self, buffer: &Self::VertexBuffer) -> Result<()> {
+        buffer.Unlock()
+    }
+
+    unsafe fn lock_index_buffer(
+        &self,
+        buffer: &Self::IndexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void> {
+        let mut data = ptr::null_mut();
+        buffer.Lock(offset, size, &mut data, flags)?;
+        Ok(data)
+    }
+
+    unsafe fn unlock_index_buffer(&self, buffer: &Self::IndexBuffer) -> Result<()> {
+        buffer.Unlock()
+    }
+
+    unsafe fn get_level_count(&self, texture: &Self::Texture) -> u32 {
+        texture.GetLevelCount()
+    }
+
+    unsafe fn get_level_desc(&self, texture: &Self::Texture, level: u32) -> Result<SurfaceDesc> {
+        let mut desc = D3DSURFACE_DESC::default();
+        texture.cast::<IDirect3DTexture9>()?.GetLevelDesc(level, &mut desc)?;
+        Ok(SurfaceDesc {
+            format: desc.Format.0,
+            usage: desc.Usage,
+            pool: desc.Pool.0 as u32,
+            width: desc.Width,
+            height: desc.Height,
+        })
+    }
+
+    unsafe fn lock_rect(
+        &self,
+        texture: &Self::Texture,
+        level: u32,
+        rect: Option<&Rect>,
+        flags: u32,
+    ) -> Result<LockedRect> {
+        let texture: IDirect3DTexture9 = texture.cast()?;
+        let rect = rect.map(RECT::from);
+        let mut locked_rect = D3DLOCKED_RECT { Pitch: 0, pBits: ptr::null_mut() };
+        texture.LockRect(
+            level,
+            &mut locked_rect,
+            rect.as_ref().map_or(ptr::null(), |r| r as *const RECT),
+            flags,
+        )?;
+        Ok(LockedRect { pitch: locked_rect.Pitch as usize, bits: locked_rect.pBits })
+    }
+
+    unsafe fn unlock_rect(&self, texture: &Self::Texture, level: u32) -> Result<()> {
+        texture.cast::<IDirect3DTexture9>()?.UnlockRect(level)
+    }
+
+    unsafe fn capture_state_block(&self, block: &Self::StateBlock) -> Result<()> {
+        block.Capture()
+    }
+
+    unsafe fn apply_state_block(&self, block: &Self::StateBlock) -> Result<()> {
+        block.Apply()
+    }
+
+    unsafe fn set_viewport(&self, viewport: &Viewport) -> Result<()> {
+        self.SetViewport(&D3DVIEWPORT9 {
+            X: viewport.x,
+            Y: viewport.y,
+            Width: viewport.width,
+            Height: viewport.height,
+            MinZ: viewport.min_z,
+            MaxZ: viewport.max_z,
+        })
+    }
+
+    unsafe fn set_pixel_shader(&self, shader: Option<&Self::PixelShader>) -> Result<()> {
+        self.SetPixelShader(shader)
+    }
+
+    unsafe fn set_vertex_shader(&self, shader: Option<&Self::VertexShader>) -> Result<()> {
+        self.SetVertexShader(shader)
+    }
+
+    unsafe fn set_vertex_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()> {
+        self.SetVertexShaderConstantF(
+            start_register,
+            constants.as_ptr().cast(),
+            constants.len() as u32,
+        )
+    }
+
+    unsafe fn set_pixel_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()> {
+        self.SetPixelShaderConstantF(
+            start_register,
+            constants.as_ptr().cast(),
+            constants.len() as u32,
+        )
+    }
+
+    unsafe fn set_render_state(&self, state: u32, value: u32) -> Result<()> {
+        self.SetRenderState(D3DRENDERSTATETYPE(state as i32), value)
+    }
+
+    unsafe fn set_texture_stage_state(&self, stage: u32, ty: u32, value: u32) -> Result<()> {
+        self.SetTextureStageState(stage, D3DTEXTURESTAGESTATETYPE(ty as i32), value)
+    }
+
+    unsafe fn set_sampler_state(&self, sampler: u32, ty: u32, value: u32) -> Result<()> {
+        self.SetSamplerState(sampler, D3DSAMPLERSTATETYPE(ty as i32), value)
+    }
+
+    unsafe fn set_transform(&self, state: u32, matrix: &Matrix4x4) -> Result<()> {
+        self.SetTransform(D3DTRANSFORMSTATETYPE(state as i32), matrix)
+    }
+
+    unsafe fn set_texture(&self, stage: u32, texture: Option<&Self::Texture>) -> Result<()> {
+        self.SetTexture(stage, texture)
+    }
+
+    unsafe fn set_scissor_rect(&self, rect: &Rect) -> Result<()> {
+        self.SetScissorRect(&RECT::from(rect))
+    }
+
+    unsafe fn set_stream_source(
+        &self,
+        stream: u32,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        stride: u32,
+    ) -> Result<()> {
+        self.SetStreamSource(stream, buffer, offset, stride)
+    }
+
+    unsafe fn set_indices(&self, buffer: &Self::IndexBuffer) -> Result<()> {
+        self.SetIndices(buffer)
+    }
+
+    unsafe fn set_fvf(&self, fvf: u32) -> Result<()> {
+        self.SetFVF(fvf)
+    }
+
+    unsafe fn draw_indexed_primitive(
+        &self,
+        primitive_type: u32,
+        base_vertex_index: i32,
+        min_vertex_index: u32,
+        num_vertices: u32,
+        start_index: u32,
+        prim_count: u32,
+    ) -> Result<()> {
+        self.DrawIndexedPrimitive(
+            D3DPRIMITIVETYPE(primitive_type as i32),
+            base_vertex_index,
+            min_vertex_index,
+            num_vertices,
+            start_index,
+            prim_count,
+        )
+    }
+}
@@ -0,0 +1,186 @@
+//! Pure helpers turning imgui draw commands into Direct3D 9 draw calls.
+
+use imgui::TextureId;
+
+use crate::device::Rect;
+
+/// The position of a draw command within [`DrawData`](imgui::DrawData).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommandIndex {
+    /// The index of the draw list.
+    pub draw_list: usize,
+    /// The index of the command within its draw list.
+    pub command: usize,
+}
+
+/// Converts a command's clip rectangle into the scissor rectangle to draw it
+/// with, or `None` if nothing of it would be visible.
+///
+/// `clip_rect` is in imgui's display coordinates and gets moved by
+/// `clip_off`, scaled by `clip_scale`, rounded to the nearest pixel and
+/// clamped to a framebuffer of `fb_size` pixels. Rectangles that end up empty,
+/// inverted or entirely outside the framebuffer are culled.
+pub fn scissor_rect(
+    clip_rect: [f32; 4],
+    clip_off: [f32; 2],
+    clip_scale: [f32; 2],
+    fb_size: [u32; 2],
+) -> Option<Rect> {
+    let to_fb = |value: f32, axis: usize| {
+        let value = ((value - clip_off[axis]) * clip_scale[axis]).round();
+        value.max(0.0).min(fb_size[axis] as f32) as i32
+    };
+    let rect = Rect {
+        left: to_fb(clip_rect[0], 0),
+        top: to_fb(clip_rect[1], 1),
+        right: to_fb(clip_rect[2], 0),
+        bottom: to_fb(clip_rect[3], 1),
+    };
+    (rect.left < rect.right && rect.top < rect.bottom).then_some(rect)
+}
+
+/// The arguments of the `DrawIndexedPrimitive` call for one
+/// `DrawCmd::Elements`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DrawParams {
+    pub(crate) base_vertex_index: i32,
+    pub(crate) num_vertices: u32,
+    pub(crate) start_index: u32,
+    pub(crate) prim_count: u32,
+}
+
+impl DrawParams {
+    /// Computes the draw call for a command of `count` indices.
+    ///
+    /// `list_vtx_offset` and `list_idx_offset` locate the command's draw list
+    /// in the shared vertex and index buffers, which hold `list_vtx_count`
+    /// vertices of it. `vtx_offset` and `idx_offset` are taken from the
+    /// command, imgui uses them to split draw lists with more vertices than a
+    /// 16-bit index can address.
+    pub(crate) fn new(
+        list_vtx_offset: usize,
+        list_idx_offset: usize,
+        list_vtx_count: usize,
+        count: usize,
+        vtx_offset: usize,
+        idx_offset: usize,
+    ) -> Self {
+        DrawParams {
+            base_vertex_index: (list_vtx_offset + vtx_offset) as i32,
+            num_vertices: list_vtx_count.saturating_sub(vtx_offset) as u32,
+            start_index: (list_idx_offset + idx_offset) as u32,
+            prim_count: (count / 3) as u32,
+        }
+    }
+}
+
+/// A run of draw commands that can be submitted with a single draw call.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Batch {
+    pub(crate) texture_id: TextureId,
+    pub(crate) scissor: Rect,
+    pub(crate) params: DrawParams,
+    /// The first command of the batch.
+    pub(crate) command: CommandIndex,
+}
+
+impl Batch {
+    /// Appends `next` to this batch if it uses the same texture, scissor rect
+    /// and base vertex and its indices directly follow the batch's, returning
+    /// whether it did.
+    pub(crate) fn merge(&mut self, next: &Batch) -> bool {
+        let params = &mut self.params;
+        let mergeable = self.texture_id == next.texture_id
+            && self.scissor == next.scissor
+            && params.base_vertex_index == next.params.base_vertex_index
+            && params.start_index + params.prim_count * 3 == next.params.start_index;
+        if mergeable {
+            params.num_vertices = params.num_vertices.max(next.params.num_vertices);
+            params.prim_count += next.params.prim_count;
+        }
+        mergeable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FB_SIZE: [u32; 2] = [800, 600];
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> Option<Rect> {
+        Some(Rect { left, top, right, bottom })
+    }
+
+    #[test]
+    fn scissor_rect_rounds_to_the_nearest_pixel() {
+        let clip_rect = [10.4, 20.6, 100.5, 200.49];
+        assert_eq!(scissor_rect(clip_rect, [0.0; 2], [1.0; 2], FB_SIZE), rect(10, 21, 101, 200));
+    }
+
+    #[test]
+    fn scissor_rect_is_clamped_to_the_framebuffer() {
+        let clip_rect = [-50.0, -10.0, 900.0, 700.0];
+        assert_eq!(scissor_rect(clip_rect, [0.0; 2], [1.0; 2], FB_SIZE), rect(0, 0, 800, 600));
+    }
+
+    #[test]
+    fn scissor_rect_applies_offset_then_scale() {
+        let clip_rect = [110.0, 60.0, 210.0, 160.25];
+        let scissor = scissor_rect(clip_rect, [100.0, 50.0], [2.0, 1.5], FB_SIZE);
+        assert_eq!(scissor, rect(20, 15, 220, 165));
+    }
+
+    #[test]
+    fn scissor_rect_culls_invisible_rects() {
+        for clip_rect in [
+            [10.0, 10.0, 10.0, 50.0],
+            [10.0, 10.0, 50.0, 10.2],
+            [50.0, 10.0, 10.0, 50.0],
+            [10.0, 50.0, 50.0, 10.0],
+            [900.0, 10.0, 1000.0, 50.0],
+            [10.0, -100.0, 50.0, -10.0],
+        ] {
+            assert_eq!(
+                scissor_rect(clip_rect, [0.0; 2], [1.0; 2], FB_SIZE),
+                None,
+                "{:?}",
+                clip_rect
+            );
+        }
+    }
+
+    #[test]
+    fn draw_params_of_a_split_draw_list() {
+        // A draw list of 80076 vertices, split by imgui at vertex 65532.
+        let first = DrawParams::new(0, 0, 80076, 98424, 0, 0);
+        assert_eq!(
+            first,
+            DrawParams {
+                base_vertex_index: 0,
+                num_vertices: 80076,
+                start_index: 0,
+                prim_count: 32808
+            }
+        );
+        let second = DrawParams::new(0, 0, 80076, 21780, 65532, 98424);
+        assert_eq!(
+            second,
+            DrawParams {
+                base_vertex_index: 65532,
+                num_vertices: 14544,
+                start_index: 98424,
+                prim_count: 7260
+            }
+        );
+    }
+
+    #[test]
+    fn draw_params_of_a_later_draw_list() {
+        let params = DrawParams::new(1000, 3000, 80076, 6, 65532, 98424);
+        assert_eq!(params.base_vertex_index, 66532);
+        assert_eq!(params.num_vertices, 14544);
+        assert_eq!(params.start_index, 101424);
+        assert_eq!(params.prim_count, 2);
+    }
+}
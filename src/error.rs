@@ -0,0 +1,214 @@
+//! The error type of the [`Renderer`].
+//!
+//! [`Renderer`]: crate::Renderer
+
+use std::{error, fmt};
+
+use imgui::TextureId;
+use windows_core::HRESULT;
+
+use crate::dds::DdsError;
+use crate::device::consts::*;
+use crate::draw::CommandIndex;
+
+/// The kind of buffer a [`RendererError::BufferCreation`] failed to create.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BufferKind {
+    /// The vertex buffer.
+    Vertex,
+    /// The index buffer.
+    Index,
+}
+
+/// The errors the [`Renderer`](crate::Renderer) reports.
+///
+/// Failed device calls keep their [`windows_core::Error`], and [`code`]
+/// returns an `HRESULT` for every variant. Errors convert from and into
+/// [`windows_core::Error`], so code written against the plain `HRESULT`
+/// results keeps working with `?`.
+///
+/// [`code`]: Self::code
+#[derive(Clone, Debug, PartialEq)]
+pub enum RendererError {
+    /// The device is lost, with either [`D3DERR_DEVICELOST`] or
+    /// [`D3DERR_DEVICENOTRESET`] as the code.
+    DeviceLost(HRESULT),
+    /// A texture id is not in the textures registry.
+    InvalidTexture {
+        /// The id that was looked up.
+        texture_id: TextureId,
+        /// The draw command referencing the texture, `None` outside of
+        /// rendering.
+        command: Option<CommandIndex>,
+    },
+    /// Creating the vertex or index buffer failed.
+    BufferCreation {
+        /// Which buffer was being created.
+        kind: BufferKind,
+        /// The requested size in bytes.
+        size: usize,
+        /// The error of `CreateVertexBuffer` or `CreateIndexBuffer`.
+        source: windows_core::Error,
+    },
+    /// Creating a texture failed.
+    TextureCreation {
+        /// The requested width in pixels.
+        width: u32,
+        /// The requested height in pixels.
+        height: u32,
+        /// The requested `D3DFMT_*` format.
+        format: u32,
+        /// The error of `CreateTexture`.
+        source: windows_core::Error,
+    },
+    /// Applying the state block that restores the device state after
+    /// rendering failed.
+    StateRestore(windows_core::Error),
+    /// A device call failed while drawing a command.
+    Draw {
+        /// The command being drawn, the first one of a batch when several
+        /// commands are drawn at once.
+        command: CommandIndex,
+        /// The error of the failed call.
+        source: windows_core::Error,
+    },
+    /// A DDS file could not be parsed.
+    Dds(DdsError),
+    /// An argument passed to the renderer is invalid, with a description of
+    /// what is wrong with it.
+    InvalidArgument {
+        /// What is wrong with the argument.
+        what: &'static str,
+    },
+    /// A texture was to be put under an id reserved by the renderer, see
+    /// [`TextureRegistry::is_reserved`](crate::TextureRegistry::is_reserved).
+    ReservedTextureId(TextureId),
+    /// An image could not be read or decoded, with the message of the file
+    /// system or the decoder.
+    InvalidImage(String),
+    /// Any other failed call.
+    Other(windows_core::Error),
+}
+
+impl RendererError {
+    /// The `HRESULT` of the error.
+    ///
+    /// Errors that did not come from a device call map to
+    /// [`D3DERR_INVALIDCALL`] or, for data that could not be decoded,
+    /// [`E_INVALIDARG`].
+    pub fn code(&self) -> HRESULT {
+        match self {
+            RendererError::DeviceLost(code) => *code,
+            RendererError::InvalidTexture { .. }
+            | RendererError::InvalidArgument { .. }
+            | RendererError::ReservedTextureId(_) => D3DERR_INVALIDCALL,
+            RendererError::BufferCreation { source, .. }
+            | RendererError::TextureCreation { source, .. }
+            | RendererError::StateRestore(source)
+            | RendererError::Draw { source, .. }
+            | RendererError::Other(source) => source.code(),
+            RendererError::Dds(_) | RendererError::InvalidImage(_) => E_INVALIDARG,
+        }
+    }
+
+    /// Attaches the command being drawn to errors that lack it.
+    pub(crate) fn at(self, command: CommandIndex) -> Self {
+        match self {
+            RendererError::InvalidTexture { texture_id, command: None } => {
+                RendererError::InvalidTexture { texture_id, command: Some(command) }
+            },
+            RendererError::Other(source) => RendererError::Draw { command, source },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::DeviceLost(code) => write!(f, "the device is lost ({})", code),
+            RendererError::InvalidTexture { texture_id, command: Some(command) } => write!(
+                f,
+                "command {} of draw list {} uses the unregistered texture {}",
+                command.command,
+                command.draw_list,
+                texture_id.id()
+            ),
+            RendererError::InvalidTexture { texture_id, command: None } => {
+                write!(f, "texture {} is not registered", texture_id.id())
+            },
+            RendererError::BufferCreation { kind, size, source } => {
+                let kind = match kind {
+                    BufferKind::Vertex => "vertex",
+                    BufferKind::Index => "index",
+                };
+                write!(f, "creating a {} byte {} buffer failed: {}", size, kind, source)
+            },
+            RendererError::TextureCreation { width, height, format, source } => write!(
+                f,
+                "creating a {}x{} texture of format {} failed: {}",
+                width, height, format, source
+            ),
+            RendererError::StateRestore(source) => {
+                write!(f, "restoring the device state failed: {}", source)
+            },
+            RendererError::Draw { command, source } => write!(
+                f,
+                "drawing command {} of draw list {} failed: {}",
+                command.command, command.draw_list, source
+            ),
+            RendererError::InvalidArgument { what } => write!(f, "invalid argument: {}", what),
+            RendererError::ReservedTextureId(texture_id) => {
+                write!(f, "texture id {} is reserved by the renderer", texture_id.id())
+            },
+            RendererError::Dds(e) => e.fmt(f),
+            RendererError::InvalidImage(message) => f.write_str(message),
+            RendererError::Other(source) => source.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RendererError::BufferCreation { source, .. }
+            | RendererError::TextureCreation { source, .. }
+            | RendererError::StateRestore(source)
+            | RendererError::Draw { source, .. }
+            | RendererError::Other(source) => Some(source),
+            RendererError::Dds(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<windows_core::Error> for RendererError {
+    fn from(e: windows_core::Error) -> Self {
+        match e.code() {
+            D3DERR_DEVICELOST | D3DERR_DEVICENOTRESET => RendererError::DeviceLost(e.code()),
+            _ => RendererError::Other(e),
+        }
+    }
+}
+
+impl From<HRESULT> for RendererError {
+    fn from(code: HRESULT) -> Self {
+        windows_core::Error::from(code).into()
+    }
+}
+
+impl From<DdsError> for RendererError {
+    fn from(e: DdsError) -> Self {
+        RendererError::Dds(e)
+    }
+}
+
+impl From<RendererError> for windows_core::Error {
+    fn from(e: RendererError) -> Self {
+        match e {
+            RendererError::Other(source) => source,
+            RendererError::DeviceLost(code) => code.into(),
+            e => windows_core::Error::new(e.code(), e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Owning handles for textures registered with a [`Renderer`].
+//!
+//! [`Renderer`]: crate::Renderer
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use imgui::TextureId;
+
+use crate::{DefaultDevice, Device};
+
+/// The ids of dropped [`TextureHandle`]s the renderer has yet to remove.
+pub(crate) type ReleaseQueue = Rc<RefCell<Vec<TextureId>>>;
+
+/// Owns a texture registered with a [`Renderer`].
+///
+/// Dropping the handle removes the texture from the renderer's registry at
+/// the end of the next [`render`] call, so the frame being drawn can still
+/// use it. The texture itself is released once the registry and the handle
+/// both let go of it.
+///
+/// [`Renderer`]: crate::Renderer
+/// [`render`]: crate::Renderer::render
+#[must_use = "the texture is removed once its handle is dropped"]
+pub struct TextureHandle<D: Device = DefaultDevice> {
+    id: TextureId,
+    texture: D::Texture,
+    release_queue: Weak<RefCell<Vec<TextureId>>>,
+}
+
+impl<D: Device> TextureHandle<D> {
+    pub(crate) fn new(id: TextureId, texture: D::Texture, release_queue: &ReleaseQueue) -> Self {
+        TextureHandle { id, texture, release_queue: Rc::downgrade(release_queue) }
+    }
+
+    /// The id to draw the texture with.
+    #[inline]
+    pub fn id(&self) -> TextureId {
+        self.id
+    }
+
+    /// The texture this handle owns.
+    #[inline]
+    pub fn texture(&self) -> &D::Texture {
+        &self.texture
+    }
+}
+
+impl<D: Device> From<&TextureHandle<D>> for TextureId {
+    #[inline]
+    fn from(handle: &TextureHandle<D>) -> Self {
+        handle.id
+    }
+}
+
+impl<D: Device> fmt::Debug for TextureHandle<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextureHandle").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl<D: Device> Drop for TextureHandle<D> {
+    fn drop(&mut self) {
+        if let Some(release_queue) = self.release_queue.upgrade() {
+            release_queue.borrow_mut().push(self.id);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Texture loading through the [`image`] crate, enabled by the `image`
+//! feature.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{Device, Renderer, RendererError, Result, TextureHandle, TextureOptions};
+
+impl<D: Device> Renderer<D> {
+    /// Decodes a PNG, JPEG or BMP image and registers it as a texture, see
+    /// [`create_texture_rgba8`](Self::create_texture_rgba8).
+    ///
+    /// Returns the handle of the texture and its size in pixels. Data that
+    /// cannot be decoded is reported as [`RendererError::InvalidImage`].
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn load_texture_from_memory(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(TextureHandle<D>, [u32; 2])> {
+        self.load_texture_from_memory_with_options(bytes, &TextureOptions::default())
+    }
+
+    /// Like [`load_texture_from_memory`](Self::load_texture_from_memory) but
+    /// with the given [`TextureOptions`].
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn load_texture_from_memory_with_options(
+        &mut self,
+        bytes: &[u8],
+        options: &TextureOptions,
+    ) -> Result<(TextureHandle<D>, [u32; 2])> {
+        let image = image::load_from_memory(bytes)
+            .map_err(|e| RendererError::InvalidImage(e.to_string()))?
+            .into_rgba8();
+        let (width, height) = image.dimensions();
+        let texture = self.create_texture_rgba8_with_options(width, height, &image, options)?;
+        Ok((texture, [width, height]))
+    }
+
+    /// Reads and decodes the image file at `path`, see
+    /// [`load_texture_from_memory`](Self::load_texture_from_memory).
+    ///
+    /// A file that cannot be read is reported as
+    /// [`RendererError::InvalidImage`] as well, naming the path.
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn load_texture_from_path(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<(TextureHandle<D>, [u32; 2])> {
+        self.load_texture_from_path_with_options(path, &TextureOptions::default())
+    }
+
+    /// Like [`load_texture_from_path`](Self::load_texture_from_path) but with
+    /// the given [`TextureOptions`].
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn load_texture_from_path_with_options(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &TextureOptions,
+    ) -> Result<(TextureHandle<D>, [u32; 2])> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|e| RendererError::InvalidImage(format!("{}: {}", path.display(), e)))?;
+        self.load_texture_from_memory_with_options(&bytes, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::recording::Call;
+    use crate::tests::Fixture;
+    use crate::{RendererError, RendererOptions, SamplerState, TextureFilter, TextureOptions};
+
+    #[test]
+    fn missing_file_is_an_invalid_image() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let result = fixture.renderer.load_texture_from_path("does/not/exist.png");
+        let Err(RendererError::InvalidImage(message)) = result else {
+            panic!("expected InvalidImage");
+        };
+        assert!(message.starts_with("does/not/exist.png: "), "{}", message);
+    }
+
+    #[test]
+    fn options_apply_to_loaded_images() {
+        let mut png = Vec::new();
+        image::RgbaImage::new(4, 2)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let sampler = SamplerState { min_filter: TextureFilter::Point, ..SamplerState::default() };
+        let options = TextureOptions { mipmaps: true, sampler };
+        fixture.device.clear_calls();
+        let (handle, size) =
+            fixture.renderer.load_texture_from_memory_with_options(&png, &options).unwrap();
+        assert_eq!(size, [4, 2]);
+        assert!(fixture
+            .device
+            .calls()
+            .iter()
+            .any(|c| matches!(c, Call::CreateTexture { width: 4, height: 2, levels: 3, .. })));
+        assert_eq!(fixture.renderer.texture_sampler(handle.id()), sampler);
+    }
+}
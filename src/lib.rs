@@ -1,25 +1,53 @@
-#![cfg(windows)]
 #![deny(missing_docs)]
 //! This crate offers a DirectX 9 renderer for the [imgui-rs](https://docs.rs/imgui/*/imgui/) rust bindings.
+//!
+//! The renderer issues all of its Direct3D calls through the [`Device`] trait,
+//! which is implemented for `IDirect3DDevice9` on Windows and by the
+//! [`RecordingDevice`](recording::RecordingDevice) everywhere.
 
-use std::{mem, ptr, slice};
+use std::{
+    collections::{HashMap, HashSet},
+    mem, slice,
+};
 
 use imgui::{
     internal::RawWrapper, BackendFlags, Context, DrawCmd, DrawCmdParams, DrawData, DrawIdx,
-    TextureId, Textures,
+    DrawListMut, FontAtlas, TextureId,
 };
 
-use windows::Win32::Graphics::Direct3D9::{
-    IDirect3DBaseTexture9, IDirect3DDevice9, IDirect3DIndexBuffer9, IDirect3DStateBlock9, IDirect3DTexture9, IDirect3DVertexBuffer9, D3DBLENDOP_ADD, D3DBLEND_INVSRCALPHA, D3DBLEND_ONE, D3DBLEND_SRCALPHA, D3DCULL_NONE, D3DFILL_SOLID, D3DFMT_A8R8G8B8, D3DFMT_INDEX16, D3DFMT_INDEX32, D3DFVF_DIFFUSE, D3DFVF_TEX1, D3DFVF_XYZ, D3DLOCKED_RECT, D3DLOCK_DISCARD, D3DPOOL_DEFAULT, D3DPT_TRIANGLELIST, D3DRS_ALPHABLENDENABLE, D3DRS_ALPHATESTENABLE, D3DRS_BLENDOP, D3DRS_CLIPPING, D3DRS_CULLMODE, D3DRS_DESTBLEND, D3DRS_DESTBLENDALPHA, D3DRS_FILLMODE, D3DRS_FOGENABLE, D3DRS_LIGHTING, D3DRS_RANGEFOGENABLE, D3DRS_SCISSORTESTENABLE, D3DRS_SEPARATEALPHABLENDENABLE, D3DRS_SHADEMODE, D3DRS_SPECULARENABLE, D3DRS_SRCBLEND, D3DRS_SRCBLENDALPHA, D3DRS_STENCILENABLE, D3DRS_ZENABLE, D3DRS_ZWRITEENABLE, D3DSAMP_MAGFILTER, D3DSAMP_MINFILTER, D3DSBT_ALL, D3DSHADE_GOURAUD, D3DTA_DIFFUSE, D3DTA_TEXTURE, D3DTEXF_LINEAR, D3DTOP_DISABLE, D3DTOP_MODULATE, D3DTRANSFORMSTATETYPE, D3DTSS_ALPHAARG1, D3DTSS_ALPHAARG2, D3DTSS_ALPHAOP, D3DTSS_COLORARG1, D3DTSS_COLORARG2, D3DTSS_COLOROP, D3DTS_PROJECTION, D3DTS_VIEW, D3DUSAGE_DYNAMIC, D3DUSAGE_WRITEONLY, D3DVIEWPORT9
-};
+#[cfg(windows)]
+use windows::Win32::Graphics::Direct3D9::IDirect3DDevice9;
+use windows_numerics::Matrix4x4;
 
-use windows::Win32::Foundation::RECT;
-use windows::Win32::Graphics::Dxgi::DXGI_ERROR_INVALID_CALL;
+mod callback;
+pub mod dds;
+pub mod device;
+pub mod draw;
+mod error;
+mod handle;
+#[cfg(feature = "image")]
+mod image_loader;
+mod pixels;
+pub mod recording;
+mod registry;
+mod shaders;
 
-use windows::core::Interface;
-use windows_numerics::Matrix4x4;
+pub use callback::DrawCallbackInfo;
+use callback::{DrawCallback, PixelShaderScope};
+use device::consts::*;
+pub use device::Device;
+use device::{Rect, Viewport};
+use draw::{Batch, CommandIndex, DrawParams};
+pub use error::{BufferKind, RendererError};
+use handle::ReleaseQueue;
+pub use handle::TextureHandle;
+pub use registry::TextureRegistry;
 
 const FONT_TEX_ID: usize = !0;
+const PLACEHOLDER_TEX_ID: usize = !1;
+/// The edge length in texels, and checkerboard squares, of the placeholder
+/// texture.
+const PLACEHOLDER_SIZE: u32 = 8;
 const D3DFVF_CUSTOMVERTEX: u32 = D3DFVF_XYZ | D3DFVF_DIFFUSE | D3DFVF_TEX1;
 
 const FALSE: u32 = 0;
@@ -28,8 +56,21 @@ const TRUE: u32 = 1;
 const VERTEX_BUF_ADD_CAPACITY: usize = 5000;
 const INDEX_BUF_ADD_CAPACITY: usize = 10000;
 
-///Reexport of windows::core::Result<T>
-pub type Result<T> = windows::core::Result<T>;
+/// The result type of the [`Renderer`], failing with a [`RendererError`].
+pub type Result<T, E = RendererError> = std::result::Result<T, E>;
+
+/// The device type a [`Renderer`] uses unless told otherwise.
+///
+/// This is `IDirect3DDevice9` on Windows and
+/// [`RecordingDevice`](recording::RecordingDevice) everywhere else.
+#[cfg(windows)]
+pub type DefaultDevice = IDirect3DDevice9;
+/// The device type a [`Renderer`] uses unless told otherwise.
+///
+/// This is `IDirect3DDevice9` on Windows and
+/// [`RecordingDevice`](recording::RecordingDevice) everywhere else.
+#[cfg(not(windows))]
+pub type DefaultDevice = recording::RecordingDevice;
 
 const MAT_IDENTITY: Matrix4x4 = Matrix4x4 {
     M11: 1.0,
@@ -50,6 +91,215 @@ const MAT_IDENTITY: Matrix4x4 = Matrix4x4 {
     M44: 1.0,
 };
 
+/// The format the font atlas is built and uploaded in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FontAtlasFormat {
+    /// Upload the atlas from [`FontAtlas::build_rgba32_texture`] as
+    /// `D3DFMT_A8R8G8B8`.
+    #[default]
+    Rgba32,
+    /// Upload the atlas from [`FontAtlas::build_alpha8_texture`] as
+    /// `D3DFMT_A8`, a quarter of the memory of [`Rgba32`](Self::Rgba32).
+    ///
+    /// Devices without `D3DFMT_A8` textures get `D3DFMT_A8L8` instead, and
+    /// `D3DFMT_A8R8G8B8` if that is unsupported as well. `D3DFMT_L8` is not
+    /// considered as the fixed function stages cannot turn its luminance
+    /// into the alpha the glyphs are blended with.
+    ///
+    /// With [`Pipeline::Programmable`] `D3DFMT_A8` is skipped too, as the
+    /// built-in pixel shader would sample it as black.
+    Alpha8,
+}
+
+/// How [`Renderer::render`] preserves the device state of the application.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StateBackupMode {
+    /// Create a `D3DSBT_ALL` state block every frame and apply it once
+    /// rendering is done.
+    #[default]
+    Full,
+    /// Capture only the states the renderer changes into a state block that
+    /// is recorded once and reused across frames.
+    ///
+    /// Frames containing callbacks added with
+    /// [`Renderer::add_draw_callback`] or pixel shaders pushed with
+    /// [`Renderer::push_pixel_shader`] are backed up like with
+    /// [`Full`](Self::Full) instead, as those may change any state or the
+    /// pixel shader constants.
+    Minimal,
+    /// Leave the device state as the renderer left it, for applications that
+    /// set up their whole state every frame anyway.
+    None,
+}
+
+/// How the renderer transforms and shades its vertices.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Pipeline {
+    /// Use the fixed function transforms and texture stages.
+    #[default]
+    FixedFunction,
+    /// Use a built-in `vs_2_0` vertex shader and `ps_2_0` pixel shader that
+    /// draw the same output, for devices or wrappers that emulate the fixed
+    /// function pipeline poorly.
+    ///
+    /// The shaders are created along with the renderer and survive device
+    /// resets. The projection is uploaded to the vertex shader constants
+    /// `c0`-`c3`.
+    Programmable,
+}
+
+/// What [`Renderer::render`] does with draw commands whose texture is not in
+/// the textures registry.
+///
+/// Regardless of the policy, each missing id is reported once through the
+/// [diagnostics hook](Renderer::set_diagnostics_hook).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InvalidTexturePolicy {
+    /// Stop rendering and return [`RendererError::InvalidTexture`].
+    #[default]
+    Fail,
+    /// Skip the command and keep rendering.
+    Skip,
+    /// Draw the command with a magenta and black checkerboard instead.
+    Placeholder,
+}
+
+/// Options a [`Renderer`] is created with.
+#[derive(Clone, Debug, Default)]
+pub struct RendererOptions {
+    /// The format of the font atlas texture.
+    pub font_atlas: FontAtlasFormat,
+    /// How the device state is preserved across [`Renderer::render`].
+    pub state_backup: StateBackupMode,
+    /// What happens to draw commands referencing unregistered textures.
+    pub invalid_texture: InvalidTexturePolicy,
+    /// Whether to draw with the fixed function pipeline or with shaders.
+    pub pipeline: Pipeline,
+    /// Draw into a gamma-correct pipeline, converting imgui's sRGB colors so
+    /// they come out as intended on an sRGB render target.
+    ///
+    /// Enables `D3DRS_SRGBWRITEENABLE`, converts the vertex colors from sRGB
+    /// to linear, and samples textures with `D3DSAMP_SRGBTEXTURE` if their
+    /// format supports it and their [`SamplerState::srgb`] is set. Opaque
+    /// colors match a target without sRGB conversion, but blending happens
+    /// on linear values, so translucent areas and antialiased edges look
+    /// lighter. Linear vertex colors are kept at 8 bits, which bands the
+    /// darkest shades slightly.
+    ///
+    /// Whether a texture format supports sRGB sampling is checked once per
+    /// texture. Creating the renderer fails with `D3DERR_NOTAVAILABLE` if the
+    /// device cannot write sRGB to the render target bound at that time.
+    pub srgb: bool,
+}
+
+/// A texture filter, mirroring `D3DTEXTUREFILTERTYPE`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureFilter {
+    /// Nearest neighbour sampling.
+    Point,
+    /// Bilinear interpolation.
+    Linear,
+    /// Anisotropic filtering, see [`SamplerState::max_anisotropy`].
+    Anisotropic,
+}
+
+impl TextureFilter {
+    fn to_d3d(self) -> u32 {
+        match self {
+            TextureFilter::Point => D3DTEXF_POINT,
+            TextureFilter::Linear => D3DTEXF_LINEAR,
+            TextureFilter::Anisotropic => D3DTEXF_ANISOTROPIC,
+        }
+    }
+}
+
+/// How texture coordinates outside of `[0, 1]` are resolved, mirroring
+/// `D3DTEXTUREADDRESS`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureAddress {
+    /// Repeat the texture.
+    Wrap,
+    /// Repeat the texture, mirroring every other repetition.
+    Mirror,
+    /// Extend the edge texels.
+    Clamp,
+    /// Use [`SamplerState::border_color`].
+    Border,
+    /// Mirror once around zero, then clamp.
+    MirrorOnce,
+}
+
+impl TextureAddress {
+    fn to_d3d(self) -> u32 {
+        match self {
+            TextureAddress::Wrap => D3DTADDRESS_WRAP,
+            TextureAddress::Mirror => D3DTADDRESS_MIRROR,
+            TextureAddress::Clamp => D3DTADDRESS_CLAMP,
+            TextureAddress::Border => D3DTADDRESS_BORDER,
+            TextureAddress::MirrorOnce => D3DTADDRESS_MIRRORONCE,
+        }
+    }
+}
+
+/// The sampler settings a texture is drawn with.
+///
+/// The default samples linearly with wrapping addressing, like the renderer
+/// always did before sampler states were configurable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerState {
+    /// The filter used when the texture is drawn smaller than its size.
+    pub min_filter: TextureFilter,
+    /// The filter used when the texture is drawn larger than its size.
+    pub mag_filter: TextureFilter,
+    /// The filter between mip levels, ignored for textures without mips.
+    pub mip_filter: TextureFilter,
+    /// The addressing mode of the u coordinate.
+    pub address_u: TextureAddress,
+    /// The addressing mode of the v coordinate.
+    pub address_v: TextureAddress,
+    /// The ARGB color used by [`TextureAddress::Border`].
+    pub border_color: u32,
+    /// The maximum anisotropy used by [`TextureFilter::Anisotropic`].
+    pub max_anisotropy: u32,
+    /// Whether the texture holds sRGB colors, which are decoded to linear
+    /// while sampling when the renderer was created with
+    /// [`RendererOptions::srgb`]. Clear it for textures with linear data.
+    pub srgb: bool,
+}
+
+impl Default for SamplerState {
+    fn default() -> Self {
+        SamplerState {
+            min_filter: TextureFilter::Linear,
+            mag_filter: TextureFilter::Linear,
+            mip_filter: TextureFilter::Linear,
+            address_u: TextureAddress::Wrap,
+            address_v: TextureAddress::Wrap,
+            border_color: 0,
+            max_anisotropy: 1,
+            srgb: true,
+        }
+    }
+}
+
+/// Options for textures created through the [`Renderer`].
+#[derive(Clone, Debug, Default)]
+pub struct TextureOptions {
+    /// Build a full mip chain with a box filter, so the texture stays smooth
+    /// when drawn smaller than its size.
+    pub mipmaps: bool,
+    /// The sampler settings the texture is drawn with.
+    pub sampler: SamplerState,
+}
+
+type DiagnosticsHook = Box<dyn FnMut(&RendererError)>;
+
+/// The shaders of [`Pipeline::Programmable`].
+struct BuiltinShaders<D: Device> {
+    vertex: D::VertexShader,
+    pixel: D::PixelShader,
+}
+
 #[repr(C)]
 struct CustomVertex {
     pos: [f32; 3],
@@ -58,25 +308,82 @@ struct CustomVertex {
 }
 
 /// A DirectX 9 renderer for (Imgui-rs)[https://docs.rs/imgui/*/imgui/].
-pub struct Renderer {
-    device: IDirect3DDevice9,
-    font_tex: IDirect3DBaseTexture9,
-    vertex_buffer: (IDirect3DVertexBuffer9, usize),
-    index_buffer: (IDirect3DIndexBuffer9, usize),
-    textures: Textures<IDirect3DBaseTexture9>,
+pub struct Renderer<D: Device = DefaultDevice> {
+    device: D,
+    options: RendererOptions,
+    font_tex: Option<D::Texture>,
+    font_format: u32,
+    vertex_buffer: Option<(D::VertexBuffer, usize)>,
+    index_buffer: Option<(D::IndexBuffer, usize)>,
+    state_block: Option<D::StateBlock>,
+    textures: TextureRegistry<D::Texture>,
+    release_queue: ReleaseQueue,
+    placeholder_tex: Option<D::Texture>,
+    diagnostics_hook: Option<DiagnosticsHook>,
+    reported_textures: HashSet<TextureId>,
+    draw_callbacks: HashMap<usize, DrawCallback<D>>,
+    shaders: Option<BuiltinShaders<D>>,
 }
 
-impl Renderer {
-    /// Creates a new renderer for the given [`IDirect3DDevice9`].
+impl<D: Device> Renderer<D> {
+    /// Creates a new renderer for the given [`Device`].
     ///
     /// # Safety
     ///
-    /// `device` must be a valid [`IDirect3DDevice9`] pointer.
+    /// The calls the renderer makes through [`Device`] must be sound for
+    /// `device` as long as the renderer lives. For `IDirect3DDevice9` that
+    /// means a valid device that is not used from another thread at the same
+    /// time, the [`RecordingDevice`](recording::RecordingDevice) has no
+    /// requirements.
+    pub unsafe fn new(ctx: &mut Context, device: D) -> Result<Self> {
+        Self::new_with_options(ctx, device, RendererOptions::default())
+    }
+
+    /// Creates a new renderer for the given [`Device`] with the given
+    /// [`RendererOptions`].
+    ///
+    /// # Safety
     ///
-    /// [`IDirect3DDevice9`]: https://docs.rs/winapi/0.3/x86_64-pc-windows-msvc/winapi/shared/d3d9/struct.IDirect3DDevice9.html
-    pub unsafe fn new(ctx: &mut Context, device: IDirect3DDevice9) -> Result<Self> {
-		let t = Self::create_font_texture(ctx.fonts(), &device)?;
-        let font_tex: IDirect3DBaseTexture9 = t.cast()?;
+    /// The calls the renderer makes through [`Device`] must be sound for
+    /// `device` as long as the renderer lives. For `IDirect3DDevice9` that
+    /// means a valid device that is not used from another thread at the same
+    /// time, the [`RecordingDevice`](recording::RecordingDevice) has no
+    /// requirements.
+    pub unsafe fn new_with_options(
+        ctx: &mut Context,
+        device: D,
+        options: RendererOptions,
+    ) -> Result<Self> {
+        if options.srgb && !Self::can_write_srgb(&device) {
+            return Err(D3DERR_NOTAVAILABLE.into());
+        }
+        let (font_tex, font_format) = Self::create_font_texture(ctx.fonts(), &device, &options)?;
+        let shaders = match options.pipeline {
+            Pipeline::Programmable => Some(BuiltinShaders {
+                vertex: device.create_vertex_shader(shaders::VERTEX_SHADER)?,
+                pixel: device.create_pixel_shader(shaders::PIXEL_SHADER)?,
+            }),
+            Pipeline::FixedFunction => None,
+        };
+        let placeholder_tex = match options.invalid_texture {
+            InvalidTexturePolicy::Placeholder => Some(Self::create_placeholder_texture(&device)?),
+            _ => None,
+        };
+        let mut textures = TextureRegistry::new();
+        let font_srgb_read = Self::can_read_srgb(&device, &options, font_format);
+        textures.set_srgb_read(TextureId::from(FONT_TEX_ID), font_srgb_read);
+        if placeholder_tex.is_some() {
+            let srgb_read = Self::can_read_srgb(&device, &options, D3DFMT_A8R8G8B8);
+            textures.set_srgb_read(TextureId::from(PLACEHOLDER_TEX_ID), srgb_read);
+            textures.set_sampler(
+                TextureId::from(PLACEHOLDER_TEX_ID),
+                SamplerState {
+                    min_filter: TextureFilter::Point,
+                    mag_filter: TextureFilter::Point,
+                    ..SamplerState::default()
+                },
+            );
+        }
 
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
         ctx.set_renderer_name(String::from(concat!(
@@ -84,167 +391,900 @@ impl Renderer {
             env!("CARGO_PKG_VERSION")
         )));
         Ok(Renderer {
-            vertex_buffer: Self::create_vertex_buffer(&device, 0)?,
-            index_buffer: Self::create_index_buffer(&device, 0)?,
+            vertex_buffer: Some(Self::create_vertex_buffer(&device, 0)?),
+            index_buffer: Some(Self::create_index_buffer(&device, 0)?),
             device,
-            font_tex,
-            textures: Textures::new(),
+            options,
+            font_tex: Some(font_tex),
+            font_format,
+            state_block: None,
+            textures,
+            release_queue: ReleaseQueue::default(),
+            placeholder_tex,
+            diagnostics_hook: None,
+            reported_textures: HashSet::new(),
+            draw_callbacks: HashMap::new(),
+            shaders,
         })
     }
 
-    /// Creates a new renderer for the given [`IDirect3DDevice9`].
+    /// Creates a new renderer for the given [`Device`].
     ///
     /// # Safety
     ///
-    /// `device` must be a valid [`IDirect3DDevice9`] pointer.
-    ///
-    /// [`IDirect3DDevice9`]: https://docs.rs/winapi/0.3/x86_64-pc-windows-msvc/winapi/shared/d3d9/struct.IDirect3DDevice9.html
-    pub unsafe fn new_raw(im_ctx: &mut imgui::Context, device: IDirect3DDevice9) -> Result<Self> {
+    /// The calls the renderer makes through [`Device`] must be sound for
+    /// `device` as long as the renderer lives. For `IDirect3DDevice9` that
+    /// means a valid device that is not used from another thread at the same
+    /// time, the [`RecordingDevice`](recording::RecordingDevice) has no
+    /// requirements.
+    pub unsafe fn new_raw(im_ctx: &mut imgui::Context, device: D) -> Result<Self> {
         Self::new(im_ctx, device)
     }
 
+    /// Releases all device objects the renderer created in `D3DPOOL_DEFAULT`.
+    ///
+    /// This has to be called before `IDirect3DDevice9::Reset`, followed by
+    /// [`create_device_objects`] once the reset succeeded. The textures
+    /// registry is left untouched, textures the application created in
+    /// `D3DPOOL_DEFAULT` have to be released and recreated by it.
+    ///
+    /// While the device objects are invalidated [`render`] draws nothing. The
+    /// renderer invalidates them by itself once it notices the device was
+    /// lost.
+    ///
+    /// [`create_device_objects`]: Self::create_device_objects
+    /// [`render`]: Self::render
+    pub fn invalidate_device_objects(&mut self) {
+        self.font_tex = None;
+        self.vertex_buffer = None;
+        self.index_buffer = None;
+        self.state_block = None;
+    }
+
+    /// Recreates the device objects released by
+    /// [`invalidate_device_objects`], rebuilding the font texture from
+    /// `fonts`.
+    ///
+    /// Does nothing if the device objects are still alive.
+    ///
+    /// [`invalidate_device_objects`]: Self::invalidate_device_objects
+    pub fn create_device_objects(&mut self, fonts: &mut FontAtlas) -> Result<()> {
+        unsafe {
+            if self.font_tex.is_none() {
+                self.reload_font_texture(fonts)?;
+            }
+            if self.vertex_buffer.is_none() {
+                self.vertex_buffer = Some(Self::create_vertex_buffer(&self.device, 0)?);
+            }
+            if self.index_buffer.is_none() {
+                self.index_buffer = Some(Self::create_index_buffer(&self.device, 0)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the font texture from `fonts`, for example after fonts were
+    /// added or the atlas was rebuilt for a different scale.
+    ///
+    /// The vertex and index buffers as well as the textures registry are kept.
+    /// Should creating the new texture fail the previous one stays in use.
+    pub fn reload_font_texture(&mut self, fonts: &mut FontAtlas) -> Result<()> {
+        let (font_tex, font_format) =
+            unsafe { Self::create_font_texture(fonts, &self.device, &self.options)? };
+        self.font_tex = Some(font_tex);
+        self.font_format = font_format;
+        let srgb_read = unsafe { Self::can_read_srgb(&self.device, &self.options, font_format) };
+        self.textures.set_srgb_read(TextureId::from(FONT_TEX_ID), srgb_read);
+        Ok(())
+    }
+
+    /// Whether the device objects are invalidated and have to be recreated
+    /// with [`create_device_objects`].
+    ///
+    /// [`create_device_objects`]: Self::create_device_objects
+    #[inline]
+    pub fn needs_device_objects(&self) -> bool {
+        self.font_tex.is_none() || self.vertex_buffer.is_none() || self.index_buffer.is_none()
+    }
+
+    /// The device this renderer draws with.
+    #[inline]
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
     /// The textures registry of this renderer.
     ///
-    /// The texture slot at !0 is reserved for the font texture, therefore the
-    /// renderer will ignore any texture inserted into said slot.
+    /// The registry allocates the ids and rejects the one reserved for the
+    /// font texture.
     #[inline]
-    pub fn textures_mut(&mut self) -> &mut Textures<IDirect3DBaseTexture9> {
+    pub fn textures_mut(&mut self) -> &mut TextureRegistry<D::Texture> {
         &mut self.textures
     }
 
     /// The textures registry of this renderer.
     #[inline]
-    pub fn textures(&self) -> &Textures<IDirect3DBaseTexture9> {
+    pub fn textures(&self) -> &TextureRegistry<D::Texture> {
         &self.textures
     }
 
+    /// Creates a texture from tightly packed RGBA8 `data` and inserts it into
+    /// the textures registry, returning a handle that removes it again once
+    /// dropped.
+    ///
+    /// The texture lives in `D3DPOOL_MANAGED` and therefore survives device
+    /// resets. Returns [`RendererError::InvalidArgument`] if `data` is
+    /// shorter than `width * height * 4` bytes.
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn create_texture_rgba8(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<TextureHandle<D>> {
+        self.create_texture_rgba8_with_options(width, height, data, &TextureOptions::default())
+    }
+
+    /// Like [`create_texture_rgba8`](Self::create_texture_rgba8) but with the
+    /// given [`TextureOptions`].
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn create_texture_rgba8_with_options(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        options: &TextureOptions,
+    ) -> Result<TextureHandle<D>> {
+        if data.len() < width as usize * height as usize * 4 {
+            return Err(RendererError::InvalidArgument {
+                what: "data is shorter than width * height * 4 bytes",
+            });
+        }
+        let texture = unsafe {
+            Self::create_managed_rgba8_texture(&self.device, width, height, data, options.mipmaps)?
+        };
+        Ok(self.insert_texture(texture, D3DFMT_A8R8G8B8, options.sampler))
+    }
+
+    /// Sets the sampler settings the texture `texture_id` is drawn with.
+    ///
+    /// This works for textures inserted through [`textures_mut`] as well as
+    /// for the font atlas, whose id is [`FontAtlas::tex_id`].
+    ///
+    /// The settings are forgotten once the texture is removed from the
+    /// registry.
+    ///
+    /// [`textures_mut`]: Self::textures_mut
+    pub fn set_texture_sampler(&mut self, texture_id: TextureId, sampler: SamplerState) {
+        self.textures.set_sampler(texture_id, sampler);
+    }
+
+    /// The sampler settings the texture `texture_id` is drawn with.
+    pub fn texture_sampler(&self, texture_id: TextureId) -> SamplerState {
+        self.textures.sampler(texture_id).unwrap_or_default()
+    }
+
+    /// Overwrites the `rect` area of a registered `D3DFMT_A8R8G8B8` texture
+    /// with tightly packed RGBA8 `data`.
+    ///
+    /// Only `rect` is locked. Updates covering a whole dynamic texture lock it
+    /// with `D3DLOCK_DISCARD` instead, so the driver does not have to wait for
+    /// draws still reading the previous contents.
+    ///
+    /// Textures with mip levels, such as those created with
+    /// [`TextureOptions::mipmaps`], get all lower levels rebuilt from the
+    /// updated top level with a box filter.
+    ///
+    /// Returns [`RendererError::InvalidTexture`] if `texture_id` is not
+    /// registered and [`RendererError::InvalidArgument`] if the texture has a
+    /// different format, `rect` does not lie within it or `data` is shorter
+    /// than the area of `rect`.
+    pub fn update_texture(&self, texture_id: TextureId, rect: Rect, data: &[u8]) -> Result<()> {
+        let texture = self
+            .textures
+            .get(texture_id)
+            .ok_or(RendererError::InvalidTexture { texture_id, command: None })?;
+        unsafe {
+            let desc = self.device.get_level_desc(texture, 0)?;
+            let in_bounds = 0 <= rect.left
+                && rect.left <= rect.right
+                && rect.right as u32 <= desc.width
+                && 0 <= rect.top
+                && rect.top <= rect.bottom
+                && rect.bottom as u32 <= desc.height;
+            if desc.format != D3DFMT_A8R8G8B8 {
+                return Err(RendererError::InvalidArgument {
+                    what: "the texture is not D3DFMT_A8R8G8B8",
+                });
+            }
+            if !in_bounds {
+                return Err(RendererError::InvalidArgument {
+                    what: "rect does not lie within the texture",
+                });
+            }
+            let w = (rect.right - rect.left) as usize;
+            let h = (rect.bottom - rect.top) as usize;
+            if data.len() < w * h * 4 {
+                return Err(RendererError::InvalidArgument {
+                    what: "data is shorter than the area of rect",
+                });
+            }
+            let whole = w == desc.width as usize && h == desc.height as usize;
+            let (lock_rect, flags) = if whole && desc.usage & D3DUSAGE_DYNAMIC != 0 {
+                (None, D3DLOCK_DISCARD)
+            } else {
+                (Some(&rect), 0)
+            };
+            let extent = (w * 4, h);
+            Self::write_texture(
+                &self.device,
+                texture,
+                0,
+                lock_rect,
+                flags,
+                extent,
+                |dst, pitch| pixels::rgba_to_bgra(data, w * 4, dst, pitch, w, h),
+            )?;
+            let levels = self.device.get_level_count(texture);
+            if levels > 1 {
+                let (width, height) = (desc.width as usize, desc.height as usize);
+                let top = if whole {
+                    data[..width * height * 4].to_vec()
+                } else {
+                    Self::read_rgba8_level(&self.device, texture, width, height)?
+                };
+                Self::write_mips(&self.device, texture, levels, &top, width, height)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Creates a texture from the contents of a DDS file and inserts it into
+    /// the textures registry, keeping its format and mip levels.
+    ///
+    /// Returns the handle of the texture and its size in pixels. Files [`Dds`]
+    /// cannot parse are reported as [`RendererError::Dds`], formats the device
+    /// does not support as `D3DERR_NOTAVAILABLE`.
+    ///
+    /// [`Dds`]: dds::Dds
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn create_texture_dds(&mut self, bytes: &[u8]) -> Result<(TextureHandle<D>, [u32; 2])> {
+        self.create_texture_dds_with_sampler(bytes, SamplerState::default())
+    }
+
+    /// Like [`create_texture_dds`](Self::create_texture_dds) but drawing the
+    /// texture with the given [`SamplerState`].
+    #[must_use = "the texture is removed once its handle is dropped"]
+    pub fn create_texture_dds_with_sampler(
+        &mut self,
+        bytes: &[u8],
+        sampler: SamplerState,
+    ) -> Result<(TextureHandle<D>, [u32; 2])> {
+        let dds = dds::Dds::parse(bytes)?;
+        unsafe {
+            self.device.check_texture_format(0, dds.format)?;
+            let texture = self
+                .device
+                .create_texture(
+                    dds.width,
+                    dds.height,
+                    dds.levels.len() as u32,
+                    0,
+                    dds.format,
+                    D3DPOOL_MANAGED,
+                )
+                .map_err(|source| RendererError::TextureCreation {
+                    width: dds.width,
+                    height: dds.height,
+                    format: dds.format,
+                    source,
+                })?;
+            for (level, data) in dds.levels.iter().enumerate() {
+                let extent = (data.pitch, data.rows);
+                Self::write_texture(
+                    &self.device,
+                    &texture,
+                    level as u32,
+                    None,
+                    0,
+                    extent,
+                    |dst, pitch| {
+                        pixels::copy_rows(data.data, data.pitch, dst, pitch, data.pitch, data.rows)
+                    },
+                )?;
+            }
+            Ok((self.insert_texture(texture, dds.format, sampler), [dds.width, dds.height]))
+        }
+    }
+
     /// Renders the given [`Ui`] with this renderer.
     ///
-    /// Should the [`DrawData`] contain an invalid texture index the renderer
-    /// will return `DXGI_ERROR_INVALID_CALL` and immediately stop rendering.
+    /// What happens to commands whose texture is not in the registry depends
+    /// on [`RendererOptions::invalid_texture`]: under
+    /// [`InvalidTexturePolicy::Fail`] rendering stops and
+    /// [`RendererError::InvalidTexture`] is returned, under
+    /// [`Skip`](InvalidTexturePolicy::Skip) the command is left out and under
+    /// [`Placeholder`](InvalidTexturePolicy::Placeholder) it is drawn with the
+    /// placeholder texture. Failed draw calls are reported as
+    /// [`RendererError::Draw`], both errors name the offending command.
+    ///
+    /// If the device is lost nothing is drawn and
+    /// [`RendererError::DeviceLost`] is returned. The
+    /// renderer then releases its device objects so the device can be reset,
+    /// after which they have to be recreated with [`create_device_objects`].
+    ///
+    /// The device state is restored even if drawing fails. An error restoring
+    /// it is returned unless drawing already failed with another one, device
+    /// errors never cause a panic.
+    ///
+    /// Textures whose [`TextureHandle`] was dropped are removed from the
+    /// registry after rendering.
     ///
     /// [`Ui`]: https://docs.rs/imgui/*/imgui/struct.Ui.html
+    /// [`create_device_objects`]: Self::create_device_objects
     pub fn render(&mut self, draw_data: &DrawData) -> Result<()> {
+        let mut frame = FrameState {
+            missing_textures: Vec::new(),
+            draw_callbacks: mem::take(&mut self.draw_callbacks),
+            pixel_shaders: Vec::new(),
+        };
+        let result = self.render_draw_data(draw_data, &mut frame);
+        self.report_missing_textures(frame.missing_textures);
+        self.remove_released_textures();
+        result
+    }
+
+    /// Adds a command to `draw_list` that calls `callback` with the device
+    /// once this renderer reaches it in [`render`](Self::render).
+    ///
+    /// The scissor rect is set to the clip rect of the command beforehand,
+    /// and the renderer sets up its own state again afterwards, so `callback`
+    /// is free to change any state. Frames with callbacks back up the whole
+    /// device state even under [`StateBackupMode::Minimal`], so the
+    /// application gets its state back as well.
+    ///
+    /// Callbacks whose clip rect lies outside of the framebuffer are not
+    /// called, callbacks the next [`render`] does not reach are dropped.
+    ///
+    /// [`render`]: Self::render
+    pub fn add_draw_callback<'ui>(
+        &mut self,
+        draw_list: &'ui DrawListMut<'ui>,
+        callback: impl FnOnce(&DrawCallbackInfo<'_, D>) + 'static,
+    ) {
+        self.add_callback_command(draw_list, DrawCallback::User(Box::new(callback)));
+    }
+
+    /// Creates a pixel shader from compiled bytecode, as produced by `fxc`,
+    /// for use with [`push_pixel_shader`](Self::push_pixel_shader).
+    ///
+    /// Returns [`RendererError::InvalidArgument`] if `bytecode` is not made
+    /// of whole 32-bit tokens, does not start with a pixel shader version
+    /// token or does not end with the end token.
+    ///
+    /// # Safety
+    ///
+    /// `bytecode` has to be a valid compiled pixel shader. `CreatePixelShader`
+    /// is not told its length and follows the instruction and comment lengths
+    /// inside it up to the end token, only the first and last token are
+    /// checked here.
+    pub unsafe fn create_pixel_shader(&self, bytecode: &[u8]) -> Result<D::PixelShader> {
+        if bytecode.is_empty() || !bytecode.len().is_multiple_of(4) {
+            return Err(RendererError::InvalidArgument {
+                what: "bytecode is not made of whole 32-bit tokens",
+            });
+        }
+        let function: Vec<u32> = bytecode
+            .chunks_exact(4)
+            .map(|token| u32::from_le_bytes([token[0], token[1], token[2], token[3]]))
+            .collect();
+        if function[0] & 0xFFFF_0000 != 0xFFFF_0000 {
+            return Err(RendererError::InvalidArgument {
+                what: "bytecode does not start with a pixel shader version token",
+            });
+        }
+        if function.len() < 2 || function[function.len() - 1] != 0x0000_FFFF {
+            return Err(RendererError::InvalidArgument {
+                what: "bytecode does not end with the end token",
+            });
+        }
+        Ok(self.device.create_pixel_shader(&function)?)
+    }
+
+    /// Adds a command to `draw_list` after which its commands are drawn with
+    /// `shader` instead of the pipeline's pixel shading, until the matching
+    /// [`pop_pixel_shader`](Self::pop_pixel_shader).
+    ///
+    /// `constants` are uploaded to the registers starting at `c0`. The shader
+    /// gets the vertex color in `v0` and the texture coordinate in `t0`, and
+    /// samples the command's texture from `s0`.
+    ///
+    /// Pushes nest and all of them end with `draw_list`, the following draw
+    /// lists start out with the pipeline's pixel shading again. imgui gives
+    /// child windows draw lists of their own, so their contents are not
+    /// affected by pushes in the parent window.
+    ///
+    /// Frames pushing pixel shaders are backed up like with
+    /// [`StateBackupMode::Full`] even under [`StateBackupMode::Minimal`], so
+    /// the application gets its pixel shader constants back.
+    pub fn push_pixel_shader<'ui>(
+        &mut self,
+        draw_list: &'ui DrawListMut<'ui>,
+        shader: &D::PixelShader,
+        constants: &[[f32; 4]],
+    ) {
+        let scope = PixelShaderScope { shader: shader.clone(), constants: constants.to_vec() };
+        self.add_callback_command(draw_list, DrawCallback::PushPixelShader(scope));
+    }
+
+    /// Adds a command to `draw_list` that returns to the pixel shader active
+    /// before the last [`push_pixel_shader`](Self::push_pixel_shader).
+    pub fn pop_pixel_shader<'ui>(&mut self, draw_list: &'ui DrawListMut<'ui>) {
+        self.add_callback_command(draw_list, DrawCallback::PopPixelShader);
+    }
+
+    fn add_callback_command<'ui>(
+        &mut self,
+        draw_list: &'ui DrawListMut<'ui>,
+        command: DrawCallback<D>,
+    ) {
+        let id = callback::next_id();
+        self.draw_callbacks.insert(id, command);
+        draw_list.add_callback(callback::marker(id)).build();
+    }
+
+    /// Sets the hook diagnostics are reported through.
+    ///
+    /// Currently these are the [`RendererError::InvalidTexture`] errors of
+    /// draw commands referencing unregistered textures, reported once per
+    /// texture id no matter the [`InvalidTexturePolicy`]. A new hook is
+    /// told about every id again.
+    pub fn set_diagnostics_hook(&mut self, hook: impl FnMut(&RendererError) + 'static) {
+        self.diagnostics_hook = Some(Box::new(hook));
+        self.reported_textures.clear();
+    }
+
+    fn report_missing_textures(&mut self, missing: Vec<(TextureId, CommandIndex)>) {
+        let Some(hook) = &mut self.diagnostics_hook else {
+            return;
+        };
+        for (texture_id, command) in missing {
+            if self.reported_textures.insert(texture_id) {
+                hook(&RendererError::InvalidTexture { texture_id, command: Some(command) });
+            }
+        }
+    }
+
+    fn insert_texture(
+        &mut self,
+        texture: D::Texture,
+        format: u32,
+        sampler: SamplerState,
+    ) -> TextureHandle<D> {
+        let texture_id = self.textures.insert(texture.clone());
+        self.textures.set_sampler(texture_id, sampler);
+        let srgb_read = unsafe { Self::can_read_srgb(&self.device, &self.options, format) };
+        self.textures.set_srgb_read(texture_id, srgb_read);
+        TextureHandle::new(texture_id, texture, &self.release_queue)
+    }
+
+    fn remove_released_textures(&mut self) {
+        for texture_id in self.release_queue.borrow_mut().drain(..) {
+            self.textures.remove(texture_id);
+        }
+    }
+
+    fn render_draw_data(&mut self, draw_data: &DrawData, frame: &mut FrameState<D>) -> Result<()> {
         if draw_data.display_size[0] < 0.0 || draw_data.display_size[1] < 0.0 {
             return Ok(());
         }
+        if let Err(e) = unsafe { self.device.test_cooperative_level() } {
+            let e = RendererError::from(e);
+            if matches!(e, RendererError::DeviceLost(_)) {
+                self.invalidate_device_objects();
+            }
+            return Err(e);
+        }
+        if self.font_tex.is_none() {
+            return Ok(());
+        }
         unsafe {
-            if self.vertex_buffer.1 < draw_data.total_vtx_count as usize {
-                self.vertex_buffer =
-                    Self::create_vertex_buffer(&self.device, draw_data.total_vtx_count as usize)?;
+            let vtx_count = draw_data.total_vtx_count as usize;
+            if !matches!(self.vertex_buffer, Some((_, len)) if len >= vtx_count) {
+                self.vertex_buffer = Some(Self::create_vertex_buffer(&self.device, vtx_count)?);
+            }
+            let idx_count = draw_data.total_idx_count as usize;
+            if !matches!(self.index_buffer, Some((_, len)) if len >= idx_count) {
+                self.index_buffer = Some(Self::create_index_buffer(&self.device, idx_count)?);
             }
-            if self.index_buffer.1 < draw_data.total_idx_count as usize {
-                self.index_buffer =
-                    Self::create_index_buffer(&self.device, draw_data.total_idx_count as usize)?;
+            let (Some(font_tex), Some((vb, _)), Some((ib, _))) =
+                (&self.font_tex, &self.vertex_buffer, &self.index_buffer)
+            else {
+                return Ok(());
+            };
+            if self.options.state_backup == StateBackupMode::Minimal && self.state_block.is_none() {
+                self.state_block = Some(self.record_state_block(vb, ib)?);
             }
 
-            let _state_guard = StateBackup::backup(&self.device)?;
+            let needs_full_backup = frame
+                .draw_callbacks
+                .values()
+                .any(|c| matches!(c, DrawCallback::User(_) | DrawCallback::PushPixelShader(_)));
+            let state_guard = match (self.options.state_backup, &self.state_block) {
+                (StateBackupMode::Full, _) => Some(StateBackup::backup(&self.device)?),
+                // The minimal block misses whatever else the callbacks change,
+                // as well as the pixel shader constants.
+                (StateBackupMode::Minimal, _) if needs_full_backup => {
+                    Some(StateBackup::backup(&self.device)?)
+                },
+                (StateBackupMode::Minimal, Some(block)) => {
+                    Some(StateBackup::capture(&self.device, block)?)
+                },
+                _ => None,
+            };
 
-            self.set_render_state(draw_data)?;
-            self.write_buffers(draw_data)?;
-            self.render_impl(draw_data)
+            let result = self
+                .set_render_state(draw_data)
+                .and_then(|()| self.write_buffers(vb, ib, draw_data))
+                .and_then(|()| self.render_impl(font_tex, vb, ib, draw_data, frame));
+            let restored = state_guard.map_or(Ok(()), |guard| guard.restore());
+            result.and(restored)
         }
     }
 
-    unsafe fn render_impl(&mut self, draw_data: &DrawData) -> Result<()> {
+    unsafe fn render_impl(
+        &self,
+        font_tex: &D::Texture,
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
+        draw_data: &DrawData,
+        frame: &mut FrameState<D>,
+    ) -> Result<()> {
         let clip_off = draw_data.display_pos;
         let clip_scale = draw_data.framebuffer_scale;
-        let mut vertex_offset = 0;
-        let mut index_offset = 0;
-        let mut last_tex = TextureId::from(FONT_TEX_ID);
-        self.device.SetTexture(0, &self.font_tex).unwrap();
-        for draw_list in draw_data.draw_lists() {
-            for cmd in draw_list.commands() {
+        let fb_size = [
+            (draw_data.display_size[0] * clip_scale[0]) as u32,
+            (draw_data.display_size[1] * clip_scale[1]) as u32,
+        ];
+        let mut list_vtx_offset = 0;
+        let mut list_idx_offset = 0;
+        let mut bound = BoundState::default();
+        let mut pending: Option<Batch> = None;
+        for (list_index, draw_list) in draw_data.draw_lists().enumerate() {
+            for (cmd_index, cmd) in draw_list.commands().enumerate() {
+                let command = CommandIndex { draw_list: list_index, command: cmd_index };
                 match cmd {
                     DrawCmd::Elements {
                         count,
-                        cmd_params: DrawCmdParams { clip_rect, texture_id, .. },
+                        cmd_params: DrawCmdParams { clip_rect, texture_id, vtx_offset, idx_offset },
                     } => {
-                        if texture_id != last_tex {
-                            let texture = if texture_id.id() == FONT_TEX_ID {
-                                &self.font_tex
-                            } else {
-                                self.textures.get(texture_id).ok_or(DXGI_ERROR_INVALID_CALL)?
-                            };
-                            self.device.SetTexture(0, texture)?;
-                            last_tex = texture_id;
-                        }
-
-                        let r: RECT = RECT {
-                            left: ((clip_rect[0] - clip_off[0]) * clip_scale[0]) as i32,
-                            top: ((clip_rect[1] - clip_off[1]) * clip_scale[1]) as i32,
-                            right: ((clip_rect[2] - clip_off[0]) * clip_scale[0]) as i32,
-                            bottom: ((clip_rect[3] - clip_off[1]) * clip_scale[1]) as i32,
+                        let Some(scissor) =
+                            draw::scissor_rect(clip_rect, clip_off, clip_scale, fb_size)
+                        else {
+                            continue;
+                        };
+                        let texture_id = if self.is_texture_valid(texture_id) {
+                            texture_id
+                        } else {
+                            frame.missing_textures.push((texture_id, command));
+                            match self.options.invalid_texture {
+                                InvalidTexturePolicy::Fail => {
+                                    return Err(RendererError::InvalidTexture {
+                                        texture_id,
+                                        command: Some(command),
+                                    });
+                                },
+                                InvalidTexturePolicy::Skip => continue,
+                                InvalidTexturePolicy::Placeholder => {
+                                    TextureId::from(PLACEHOLDER_TEX_ID)
+                                },
+                            }
                         };
-                        self.device.SetScissorRect(&r)?;
-                        self.device.DrawIndexedPrimitive(
-                            D3DPT_TRIANGLELIST,
-                            vertex_offset as i32,
-                            0,
-                            draw_list.vtx_buffer().len() as u32,
-                            index_offset as u32,
-                            count as u32 / 3,
-                        )?;
-                        index_offset += count;
+                        let params = DrawParams::new(
+                            list_vtx_offset,
+                            list_idx_offset,
+                            draw_list.vtx_buffer().len(),
+                            count,
+                            vtx_offset,
+                            idx_offset,
+                        );
+                        let batch = Batch { texture_id, scissor, params, command };
+                        if !pending.as_mut().is_some_and(|pending| pending.merge(&batch)) {
+                            if let Some(pending) = pending.replace(batch) {
+                                self.draw_batch(font_tex, &pending, &mut bound)?;
+                            }
+                        }
+                    },
+                    DrawCmd::ResetRenderState => {
+                        if let Some(pending) = pending.take() {
+                            self.draw_batch(font_tex, &pending, &mut bound)?;
+                        }
+                        self.reset_render_state(vb, ib, draw_data, frame.pixel_shaders.last())
+                            .map_err(|e| e.at(command))?;
+                        bound = BoundState::default();
                     },
-                    DrawCmd::ResetRenderState => self.set_render_state(draw_data)?,
                     DrawCmd::RawCallback { callback, raw_cmd } => {
-                        callback(draw_list.raw(), raw_cmd)
+                        if let Some(pending) = pending.take() {
+                            self.draw_batch(font_tex, &pending, &mut bound)?;
+                        }
+                        callback::take_invoked();
+                        callback(draw_list.raw(), raw_cmd);
+                        let draw_callback = callback::take_invoked()
+                            .and_then(|id| frame.draw_callbacks.remove(&id));
+                        if let Some(draw_callback) = draw_callback {
+                            let clip = (*raw_cmd).ClipRect;
+                            let clip_rect = [clip.x, clip.y, clip.z, clip.w];
+                            self.run_callback_command(
+                                draw_callback,
+                                clip_rect,
+                                vb,
+                                ib,
+                                draw_data,
+                                frame,
+                            )
+                            .map_err(|e| e.at(command))?;
+                        }
+                        bound = BoundState::default();
                     },
                 }
             }
-            vertex_offset += draw_list.vtx_buffer().len();
+            if !frame.pixel_shaders.is_empty() {
+                // Pixel shader scopes end with their draw list.
+                if let Some(pending) = pending.take() {
+                    self.draw_batch(font_tex, &pending, &mut bound)?;
+                }
+                frame.pixel_shaders.clear();
+                self.bind_pixel_shader(None)?;
+            }
+            list_vtx_offset += draw_list.vtx_buffer().len();
+            list_idx_offset += draw_list.idx_buffer().len();
+        }
+        if let Some(pending) = pending {
+            self.draw_batch(font_tex, &pending, &mut bound)?;
+        }
+        Ok(())
+    }
+
+    /// Carries out a command added through
+    /// [`add_callback_command`](Self::add_callback_command).
+    unsafe fn run_callback_command(
+        &self,
+        command: DrawCallback<D>,
+        clip_rect: [f32; 4],
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
+        draw_data: &DrawData,
+        frame: &mut FrameState<D>,
+    ) -> Result<()> {
+        let callback = match command {
+            DrawCallback::User(callback) => callback,
+            DrawCallback::PushPixelShader(scope) => {
+                self.bind_pixel_shader(Some(&scope))?;
+                frame.pixel_shaders.push(scope);
+                return Ok(());
+            },
+            DrawCallback::PopPixelShader => {
+                frame.pixel_shaders.pop();
+                return self.bind_pixel_shader(frame.pixel_shaders.last());
+            },
+        };
+        let fb_size = [
+            (draw_data.display_size[0] * draw_data.framebuffer_scale[0]) as u32,
+            (draw_data.display_size[1] * draw_data.framebuffer_scale[1]) as u32,
+        ];
+        let Some(clip_rect) = draw::scissor_rect(
+            clip_rect,
+            draw_data.display_pos,
+            draw_data.framebuffer_scale,
+            fb_size,
+        ) else {
+            return Ok(());
+        };
+        self.device.set_scissor_rect(&clip_rect)?;
+        callback(&DrawCallbackInfo {
+            device: &self.device,
+            clip_rect,
+            viewport: viewport(draw_data),
+        });
+        self.reset_render_state(vb, ib, draw_data, frame.pixel_shaders.last())
+    }
+
+    /// Binds the pixel shader of `scope` and uploads its constants, or
+    /// returns to the pixel shading of the pipeline.
+    unsafe fn bind_pixel_shader(&self, scope: Option<&PixelShaderScope<D>>) -> Result<()> {
+        let Some(scope) = scope else {
+            let builtin = self.shaders.as_ref().map(|shaders| &shaders.pixel);
+            return Ok(self.device.set_pixel_shader(builtin)?);
+        };
+        self.device.set_pixel_shader(Some(&scope.shader))?;
+        if !scope.constants.is_empty() {
+            self.device.set_pixel_shader_constant_f(0, &scope.constants)?;
         }
         Ok(())
     }
 
-    unsafe fn set_render_state(&mut self, draw_data: &DrawData) -> Result<()> {
-        let fb_width = draw_data.display_size[0] * draw_data.framebuffer_scale[0];
-        let fb_height = draw_data.display_size[1] * draw_data.framebuffer_scale[1];
+    /// Draws `batch`, skipping the texture and scissor rect changes that
+    /// `bound` says are already in place.
+    unsafe fn draw_batch(
+        &self,
+        font_tex: &D::Texture,
+        batch: &Batch,
+        bound: &mut BoundState,
+    ) -> Result<()> {
+        let drawn = (|| -> Result<()> {
+            if bound.texture_id != Some(batch.texture_id) {
+                self.bind_texture(font_tex, batch.texture_id)?;
+                bound.texture_id = Some(batch.texture_id);
+            }
+            if bound.scissor != Some(batch.scissor) {
+                self.device.set_scissor_rect(&batch.scissor)?;
+                bound.scissor = Some(batch.scissor);
+            }
+            let params = &batch.params;
+            self.device.draw_indexed_primitive(
+                D3DPT_TRIANGLELIST,
+                params.base_vertex_index,
+                0,
+                params.num_vertices,
+                params.start_index,
+                params.prim_count,
+            )?;
+            Ok(())
+        })();
+        drawn.map_err(|e| e.at(batch.command))
+    }
+
+    fn is_texture_valid(&self, texture_id: TextureId) -> bool {
+        texture_id.id() == FONT_TEX_ID || self.textures.get(texture_id).is_some()
+    }
+
+    unsafe fn bind_texture(&self, font_tex: &D::Texture, texture_id: TextureId) -> Result<()> {
+        let is_font = texture_id.id() == FONT_TEX_ID;
+        let texture = match texture_id.id() {
+            FONT_TEX_ID => Some(font_tex),
+            PLACEHOLDER_TEX_ID => self.placeholder_tex.as_ref(),
+            _ => self.textures.get(texture_id),
+        }
+        .ok_or(RendererError::InvalidTexture { texture_id, command: None })?;
+        self.device.set_texture(0, Some(texture))?;
 
-        let vp = D3DVIEWPORT9 {
-            X: 0,
-            Y: 0,
-            Width: fb_width as _,
-            Height: fb_height as _,
-            MinZ: 0.0,
-            MaxZ: 1.0,
+        let sampler = self.texture_sampler(texture_id);
+        let mip_filter = if self.device.get_level_count(texture) > 1 {
+            sampler.mip_filter.to_d3d()
+        } else {
+            D3DTEXF_NONE
         };
+        self.device.set_sampler_state(0, D3DSAMP_MINFILTER, sampler.min_filter.to_d3d())?;
+        self.device.set_sampler_state(0, D3DSAMP_MAGFILTER, sampler.mag_filter.to_d3d())?;
+        self.device.set_sampler_state(0, D3DSAMP_MIPFILTER, mip_filter)?;
+        self.device.set_sampler_state(0, D3DSAMP_ADDRESSU, sampler.address_u.to_d3d())?;
+        self.device.set_sampler_state(0, D3DSAMP_ADDRESSV, sampler.address_v.to_d3d())?;
+        self.device.set_sampler_state(0, D3DSAMP_BORDERCOLOR, sampler.border_color)?;
+        self.device.set_sampler_state(0, D3DSAMP_MAXANISOTROPY, sampler.max_anisotropy)?;
+        // Textures inserted through `textures_mut` are queried once here.
+        let srgb_texture = self.options.srgb
+            && sampler.srgb
+            && self.textures.srgb_read(texture_id, || {
+                self.device
+                    .get_level_desc(texture, 0)
+                    .is_ok_and(|desc| Self::can_read_srgb(&self.device, &self.options, desc.format))
+            });
+        self.device.set_sampler_state(0, D3DSAMP_SRGBTEXTURE, srgb_texture as u32)?;
+        if self.font_format == D3DFMT_A8 {
+            // D3DFMT_A8 samples as black, so the glyphs take their color from
+            // the vertices alone
+            let color_op = if is_font { D3DTOP_SELECTARG2 } else { D3DTOP_MODULATE };
+            self.device.set_texture_stage_state(0, D3DTSS_COLOROP, color_op)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the device can write sRGB to the render target bound now.
+    unsafe fn can_write_srgb(device: &D) -> bool {
+        device.get_render_target_format(0).is_ok_and(|format| {
+            device.check_surface_format(D3DUSAGE_QUERY_SRGBWRITE, format).is_ok()
+        })
+    }
+
+    /// Whether textures of `format` are sampled with `D3DSAMP_SRGBTEXTURE`,
+    /// which is never the case without [`RendererOptions::srgb`].
+    unsafe fn can_read_srgb(device: &D, options: &RendererOptions, format: u32) -> bool {
+        options.srgb && device.check_texture_format(D3DUSAGE_QUERY_SRGBREAD, format).is_ok()
+    }
 
+    /// Sets the shaders, render states and texture stage states the renderer
+    /// draws with, which do not depend on the draw data.
+    unsafe fn set_pipeline_state(&self) -> Result<()> {
         let device = &self.device;
-        device.SetViewport(&vp)?;
-        device.SetPixelShader(None)?;
-        device.SetVertexShader(None)?;
-        device.SetRenderState(D3DRS_FILLMODE, D3DFILL_SOLID.0 as u32)?;
-        device.SetRenderState(D3DRS_SHADEMODE, D3DSHADE_GOURAUD.0 as u32)?;
-        device.SetRenderState(D3DRS_ZWRITEENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_ALPHATESTENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_CULLMODE, D3DCULL_NONE.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_ZENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_ALPHABLENDENABLE, TRUE)?;
-        device.SetRenderState(D3DRS_BLENDOP, D3DBLENDOP_ADD.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_SRCBLEND, D3DBLEND_SRCALPHA.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_DESTBLEND, D3DBLEND_INVSRCALPHA.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_SEPARATEALPHABLENDENABLE, TRUE)?;
-        device.SetRenderState(D3DRS_SRCBLENDALPHA, D3DBLEND_ONE.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_DESTBLENDALPHA, D3DBLEND_INVSRCALPHA.0.try_into().unwrap())?;
-        device.SetRenderState(D3DRS_SCISSORTESTENABLE, TRUE)?;
-        device.SetRenderState(D3DRS_FOGENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_RANGEFOGENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_SPECULARENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_STENCILENABLE, FALSE)?;
-        device.SetRenderState(D3DRS_CLIPPING, TRUE)?;
-        device.SetRenderState(D3DRS_LIGHTING, FALSE)?;
-        device.SetTextureStageState(0, D3DTSS_COLOROP, D3DTOP_MODULATE.0 as u32)?;
-        device.SetTextureStageState(0, D3DTSS_COLORARG1, D3DTA_TEXTURE)?;
-        device.SetTextureStageState(0, D3DTSS_COLORARG2, D3DTA_DIFFUSE)?;
-        device.SetTextureStageState(0, D3DTSS_ALPHAOP, D3DTOP_MODULATE.0 as u32)?;
-        device.SetTextureStageState(0, D3DTSS_ALPHAARG1, D3DTA_TEXTURE)?;
-        device.SetTextureStageState(0, D3DTSS_ALPHAARG2, D3DTA_DIFFUSE)?;
-        device.SetTextureStageState(1, D3DTSS_COLOROP, D3DTOP_DISABLE.0 as u32)?;
-        device.SetTextureStageState(1, D3DTSS_ALPHAOP, D3DTOP_DISABLE.0 as u32)?;
-        device.SetSamplerState(0, D3DSAMP_MINFILTER, D3DTEXF_LINEAR.0 as u32)?;
-        device.SetSamplerState(0, D3DSAMP_MAGFILTER, D3DTEXF_LINEAR.0 as u32)?;
+        device.set_pixel_shader(self.shaders.as_ref().map(|shaders| &shaders.pixel))?;
+        device.set_vertex_shader(self.shaders.as_ref().map(|shaders| &shaders.vertex))?;
+        device.set_render_state(D3DRS_FILLMODE, D3DFILL_SOLID)?;
+        device.set_render_state(D3DRS_SHADEMODE, D3DSHADE_GOURAUD)?;
+        device.set_render_state(D3DRS_ZWRITEENABLE, FALSE)?;
+        device.set_render_state(D3DRS_ALPHATESTENABLE, FALSE)?;
+        device.set_render_state(D3DRS_CULLMODE, D3DCULL_NONE)?;
+        device.set_render_state(D3DRS_ZENABLE, FALSE)?;
+        device.set_render_state(D3DRS_ALPHABLENDENABLE, TRUE)?;
+        device.set_render_state(D3DRS_BLENDOP, D3DBLENDOP_ADD)?;
+        device.set_render_state(D3DRS_SRCBLEND, D3DBLEND_SRCALPHA)?;
+        device.set_render_state(D3DRS_DESTBLEND, D3DBLEND_INVSRCALPHA)?;
+        device.set_render_state(D3DRS_SEPARATEALPHABLENDENABLE, TRUE)?;
+        device.set_render_state(D3DRS_SRCBLENDALPHA, D3DBLEND_ONE)?;
+        device.set_render_state(D3DRS_DESTBLENDALPHA, D3DBLEND_INVSRCALPHA)?;
+        device.set_render_state(D3DRS_SCISSORTESTENABLE, TRUE)?;
+        device.set_render_state(D3DRS_FOGENABLE, FALSE)?;
+        device.set_render_state(D3DRS_RANGEFOGENABLE, FALSE)?;
+        device.set_render_state(D3DRS_SPECULARENABLE, FALSE)?;
+        device.set_render_state(D3DRS_STENCILENABLE, FALSE)?;
+        device.set_render_state(D3DRS_CLIPPING, TRUE)?;
+        device.set_render_state(D3DRS_LIGHTING, FALSE)?;
+        device.set_render_state(D3DRS_SRGBWRITEENABLE, self.options.srgb as u32)?;
+        device.set_texture_stage_state(0, D3DTSS_COLOROP, D3DTOP_MODULATE)?;
+        device.set_texture_stage_state(0, D3DTSS_COLORARG1, D3DTA_TEXTURE)?;
+        device.set_texture_stage_state(0, D3DTSS_COLORARG2, D3DTA_DIFFUSE)?;
+        device.set_texture_stage_state(0, D3DTSS_ALPHAOP, D3DTOP_MODULATE)?;
+        device.set_texture_stage_state(0, D3DTSS_ALPHAARG1, D3DTA_TEXTURE)?;
+        device.set_texture_stage_state(0, D3DTSS_ALPHAARG2, D3DTA_DIFFUSE)?;
+        device.set_texture_stage_state(1, D3DTSS_COLOROP, D3DTOP_DISABLE)?;
+        device.set_texture_stage_state(1, D3DTSS_ALPHAOP, D3DTOP_DISABLE)?;
+        Ok(())
+    }
+
+    /// Records a state block holding every state [`render`](Self::render)
+    /// changes, for [`StateBackupMode::Minimal`].
+    ///
+    /// Only which states are set matters while recording, the values are
+    /// replaced with the current ones each time the block is captured.
+    unsafe fn record_state_block(
+        &self,
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
+    ) -> Result<D::StateBlock> {
+        let device = &self.device;
+        device.begin_state_block()?;
+        let recorded = (|| -> Result<()> {
+            device.set_viewport(&Viewport::default())?;
+            self.set_pipeline_state()?;
+            match self.options.pipeline {
+                Pipeline::FixedFunction => {
+                    for state in [D3DTS_WORLD, D3DTS_VIEW, D3DTS_PROJECTION] {
+                        device.set_transform(state, &MAT_IDENTITY)?;
+                    }
+                },
+                Pipeline::Programmable => {
+                    device.set_vertex_shader_constant_f(0, &transposed(&MAT_IDENTITY))?;
+                },
+            }
+            device.set_texture(0, None)?;
+            for ty in [
+                D3DSAMP_MINFILTER,
+                D3DSAMP_MAGFILTER,
+                D3DSAMP_MIPFILTER,
+                D3DSAMP_ADDRESSU,
+                D3DSAMP_ADDRESSV,
+                D3DSAMP_BORDERCOLOR,
+                D3DSAMP_MAXANISOTROPY,
+                D3DSAMP_SRGBTEXTURE,
+            ] {
+                device.set_sampler_state(0, ty, 0)?;
+            }
+            device.set_scissor_rect(&Rect::default())?;
+            device.set_stream_source(0, vb, 0, mem::size_of::<CustomVertex>() as u32)?;
+            device.set_indices(ib)?;
+            device.set_fvf(D3DFVF_CUSTOMVERTEX)?;
+            Ok(())
+        })();
+        // Recording has to end even if a call failed, the device would
+        // swallow every later call otherwise.
+        let block = device.end_state_block();
+        recorded?;
+        Ok(block?)
+    }
+
+    unsafe fn set_render_state(&self, draw_data: &DrawData) -> Result<()> {
+        let device = &self.device;
+        device.set_viewport(&viewport(draw_data))?;
+        self.set_pipeline_state()?;
 
         let l = draw_data.display_pos[0] + 0.5;
         let r = draw_data.display_pos[0] + draw_data.display_size[0] + 0.5;
@@ -269,60 +1309,68 @@ impl Renderer {
             M44: 1.0,
         };
 
-        device.SetTransform(D3DTRANSFORMSTATETYPE(0), &MAT_IDENTITY)?;
-        device.SetTransform(D3DTS_VIEW, &MAT_IDENTITY)?;
-        device.SetTransform(D3DTS_PROJECTION, &mat_projection)?;
+        match self.options.pipeline {
+            Pipeline::FixedFunction => {
+                device.set_transform(D3DTS_WORLD, &MAT_IDENTITY)?;
+                device.set_transform(D3DTS_VIEW, &MAT_IDENTITY)?;
+                device.set_transform(D3DTS_PROJECTION, &mat_projection)?;
+            },
+            Pipeline::Programmable => {
+                device.set_vertex_shader_constant_f(0, &transposed(&mat_projection))?;
+            },
+        }
         Ok(())
     }
 
-    unsafe fn lock_buffers<'v, 'i>(
-        vb: &'v mut IDirect3DVertexBuffer9,
-        ib: &'i mut IDirect3DIndexBuffer9,
+    unsafe fn lock_buffers(
+        &self,
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
         vtx_count: usize,
         idx_count: usize,
-    ) -> Result<(&'v mut [CustomVertex], &'i mut [DrawIdx])> {
-        let mut vtx_dst: *mut CustomVertex = ptr::null_mut();
-        let mut idx_dst: *mut DrawIdx = ptr::null_mut();
-
-        vb.Lock(
+    ) -> Result<(*mut CustomVertex, *mut DrawIdx)> {
+        let vtx_dst = self.device.lock_vertex_buffer(
+            vb,
             0,
             (vtx_count * mem::size_of::<CustomVertex>()) as u32,
-            &mut vtx_dst as *mut _ as _,
-            D3DLOCK_DISCARD as u32,
+            D3DLOCK_DISCARD,
         )?;
 
-        match ib.Lock(
+        match self.device.lock_index_buffer(
+            ib,
             0,
             (idx_count * mem::size_of::<DrawIdx>()) as u32,
-            &mut idx_dst as *mut _ as _,
-            D3DLOCK_DISCARD as u32,
+            D3DLOCK_DISCARD,
         ) {
-            Ok(_) => Ok((
-                slice::from_raw_parts_mut(vtx_dst, vtx_count),
-                slice::from_raw_parts_mut(idx_dst, idx_count),
-            )),
+            Ok(idx_dst) => Ok((vtx_dst.cast(), idx_dst.cast())),
             Err(e) => {
-                vb.Unlock()?;
-                Err(e)
+                self.device.unlock_vertex_buffer(vb)?;
+                Err(e.into())
             },
         }
     }
 
-    unsafe fn write_buffers(&mut self, draw_data: &DrawData) -> Result<()> {
-        let (mut vtx_dst, mut idx_dst) = Self::lock_buffers(
-            &mut self.vertex_buffer.0,
-            &mut self.index_buffer.0,
-            draw_data.total_vtx_count as usize,
-            draw_data.total_idx_count as usize,
-        )?;
+    unsafe fn write_buffers(
+        &self,
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
+        draw_data: &DrawData,
+    ) -> Result<()> {
+        let vtx_count = draw_data.total_vtx_count as usize;
+        let idx_count = draw_data.total_idx_count as usize;
+        let (vtx_dst, idx_dst) = self.lock_buffers(vb, ib, vtx_count, idx_count)?;
+        let mut vtx_dst = slice::from_raw_parts_mut(vtx_dst, vtx_count);
+        let mut idx_dst = slice::from_raw_parts_mut(idx_dst, idx_count);
 
         for (vbuf, ibuf) in
             draw_data.draw_lists().map(|draw_list| (draw_list.vtx_buffer(), draw_list.idx_buffer()))
         {
             for (vertex, vtx_dst) in vbuf.iter().zip(vtx_dst.iter_mut()) {
+                let col =
+                    if self.options.srgb { pixels::srgb_to_linear(vertex.col) } else { vertex.col };
                 *vtx_dst = CustomVertex {
                     pos: [vertex.pos[0], vertex.pos[1], 0.0],
-                    col: [vertex.col[2], vertex.col[1], vertex.col[0], vertex.col[3]],
+                    col: [col[2], col[1], col[0], col[3]],
                     uv: [vertex.uv[0], vertex.uv[1]],
                 };
             }
@@ -330,108 +1378,1192 @@ impl Renderer {
             vtx_dst = &mut vtx_dst[vbuf.len()..];
             idx_dst = &mut idx_dst[ibuf.len()..];
         }
-        self.vertex_buffer.0.Unlock()?;
-        self.index_buffer.0.Unlock()?;
-        self.device.SetStreamSource(
-            0,
-            &self.vertex_buffer.0,
-            0,
-            mem::size_of::<CustomVertex>() as u32,
-        )?;
-        self.device.SetIndices(&self.index_buffer.0)?;
-        self.device.SetFVF(D3DFVF_CUSTOMVERTEX)?;
+        self.device.unlock_vertex_buffer(vb)?;
+        self.device.unlock_index_buffer(ib)?;
+        self.bind_buffers(vb, ib)
+    }
+
+    unsafe fn bind_buffers(&self, vb: &D::VertexBuffer, ib: &D::IndexBuffer) -> Result<()> {
+        self.device.set_stream_source(0, vb, 0, mem::size_of::<CustomVertex>() as u32)?;
+        self.device.set_indices(ib)?;
+        self.device.set_fvf(D3DFVF_CUSTOMVERTEX)?;
+        Ok(())
+    }
+
+    /// Sets up the render state, binds the buffers and the pushed pixel
+    /// shader again after a callback may have changed them.
+    unsafe fn reset_render_state(
+        &self,
+        vb: &D::VertexBuffer,
+        ib: &D::IndexBuffer,
+        draw_data: &DrawData,
+        pixel_shader: Option<&PixelShaderScope<D>>,
+    ) -> Result<()> {
+        self.set_render_state(draw_data)?;
+        self.bind_buffers(vb, ib)?;
+        if pixel_shader.is_some() {
+            self.bind_pixel_shader(pixel_shader)?;
+        }
         Ok(())
     }
 
     unsafe fn create_vertex_buffer(
-        device: &IDirect3DDevice9,
+        device: &D,
         vtx_count: usize,
-    ) -> Result<(IDirect3DVertexBuffer9, usize)> {
+    ) -> Result<(D::VertexBuffer, usize)> {
         let len = vtx_count + VERTEX_BUF_ADD_CAPACITY;
-        let mut vertex_buffer: Option<IDirect3DVertexBuffer9> = None;
-        device.CreateVertexBuffer(
-            (len * mem::size_of::<CustomVertex>()) as u32,
-            (D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY) as u32,
-            D3DFVF_CUSTOMVERTEX,
-            D3DPOOL_DEFAULT,
-            &mut vertex_buffer,
-            ptr::null_mut(),
-        )?;
-        Ok((vertex_buffer.unwrap(), len))
+        let size = len * mem::size_of::<CustomVertex>();
+        let vertex_buffer = device
+            .create_vertex_buffer(
+                size as u32,
+                D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY,
+                D3DFVF_CUSTOMVERTEX,
+                D3DPOOL_DEFAULT,
+            )
+            .map_err(|source| RendererError::BufferCreation {
+                kind: BufferKind::Vertex,
+                size,
+                source,
+            })?;
+        Ok((vertex_buffer, len))
     }
 
-    unsafe fn create_index_buffer(
-        device: &IDirect3DDevice9,
-        idx_count: usize,
-    ) -> Result<(IDirect3DIndexBuffer9, usize)> {
+    unsafe fn create_index_buffer(device: &D, idx_count: usize) -> Result<(D::IndexBuffer, usize)> {
         let len = idx_count + INDEX_BUF_ADD_CAPACITY;
-        let mut index_buffer: Option<IDirect3DIndexBuffer9> = None;
-
-        device.CreateIndexBuffer(
-            (len * mem::size_of::<DrawIdx>()) as u32,
-            (D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY) as u32,
-            if mem::size_of::<DrawIdx>() == 2 { D3DFMT_INDEX16 } else { D3DFMT_INDEX32 },
-            D3DPOOL_DEFAULT,
-            &mut index_buffer,
-            ptr::null_mut(),
-        )?;
-        Ok((index_buffer.unwrap(), len))
+        let size = len * mem::size_of::<DrawIdx>();
+        let index_buffer = device
+            .create_index_buffer(
+                size as u32,
+                D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY,
+                if mem::size_of::<DrawIdx>() == 2 { D3DFMT_INDEX16 } else { D3DFMT_INDEX32 },
+                D3DPOOL_DEFAULT,
+            )
+            .map_err(|source| RendererError::BufferCreation {
+                kind: BufferKind::Index,
+                size,
+                source,
+            })?;
+        Ok((index_buffer, len))
     }
 
-    // FIXME, imgui hands us an rgba texture while we make dx9 think it receives an
-    // argb texture
     unsafe fn create_font_texture(
-        fonts: &mut imgui::FontAtlas,
-        device: &IDirect3DDevice9,
-    ) -> Result<IDirect3DTexture9> {
-        let texture = fonts.build_rgba32_texture();
-        let mut texture_handle: Option<IDirect3DTexture9> = None;
-
-        device.CreateTexture(
-            texture.width,
-            texture.height,
-            1,
-            D3DUSAGE_DYNAMIC as u32,
-            D3DFMT_A8R8G8B8,
-            D3DPOOL_DEFAULT,
-            &mut texture_handle,
-            ptr::null_mut(),
-        )?;
+        fonts: &mut FontAtlas,
+        device: &D,
+        options: &RendererOptions,
+    ) -> Result<(D::Texture, u32)> {
+        let (result_texture, format) = match options.font_atlas {
+            FontAtlasFormat::Rgba32 => {
+                let texture = fonts.build_rgba32_texture();
+                let result_texture = Self::create_font_atlas_texture(
+                    device,
+                    texture.width,
+                    texture.height,
+                    D3DFMT_A8R8G8B8,
+                )?;
+                let (width, height) = (texture.width as usize, texture.height as usize);
+                Self::write_texture(
+                    device,
+                    &result_texture,
+                    0,
+                    None,
+                    0,
+                    (width * 4, height),
+                    |dst, pitch| {
+                        pixels::rgba_to_bgra(texture.data, width * 4, dst, pitch, width, height)
+                    },
+                )?;
+                (result_texture, D3DFMT_A8R8G8B8)
+            },
+            FontAtlasFormat::Alpha8 => {
+                let format = [D3DFMT_A8, D3DFMT_A8L8]
+                    .into_iter()
+                    .filter(|&format| {
+                        format != D3DFMT_A8 || options.pipeline == Pipeline::FixedFunction
+                    })
+                    .find(|&format| device.check_texture_format(D3DUSAGE_DYNAMIC, format).is_ok())
+                    .unwrap_or(D3DFMT_A8R8G8B8);
+                let texture = fonts.build_alpha8_texture();
+                let result_texture =
+                    Self::create_font_atlas_texture(device, texture.width, texture.height, format)?;
+                let (width, height) = (texture.width as usize, texture.height as usize);
+                let row_len = width * pixels::bytes_per_pixel(format);
+                Self::write_texture(
+                    device,
+                    &result_texture,
+                    0,
+                    None,
+                    0,
+                    (row_len, height),
+                    |dst, pitch| {
+                        pixels::alpha8_to(format, texture.data, width, dst, pitch, width, height)
+                    },
+                )?;
+                (result_texture, format)
+            },
+        };
+        fonts.tex_id = TextureId::from(FONT_TEX_ID);
+        Ok((result_texture, format))
+    }
 
-        let mut locked_rect: D3DLOCKED_RECT = D3DLOCKED_RECT { Pitch: 0, pBits: ptr::null_mut() };
-        let result_texture = texture_handle.unwrap();
+    /// Creates a `D3DPOOL_MANAGED` texture from tightly packed RGBA8 `data`,
+    /// optionally with a box filtered mip chain.
+    unsafe fn create_managed_rgba8_texture(
+        device: &D,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        mipmaps: bool,
+    ) -> Result<D::Texture> {
+        let (w, h) = (width as usize, height as usize);
+        let levels = if mipmaps { 32 - width.max(height).leading_zeros() } else { 1 };
+        let texture = device
+            .create_texture(width, height, levels, 0, D3DFMT_A8R8G8B8, D3DPOOL_MANAGED)
+            .map_err(|source| RendererError::TextureCreation {
+                width,
+                height,
+                format: D3DFMT_A8R8G8B8,
+                source,
+            })?;
+        Self::write_texture(device, &texture, 0, None, 0, (w * 4, h), |dst, pitch| {
+            pixels::rgba_to_bgra(data, w * 4, dst, pitch, w, h)
+        })?;
+        Self::write_mips(device, &texture, levels, data, w, h)?;
+        Ok(texture)
+    }
 
-        result_texture.LockRect(0, &mut locked_rect, ptr::null_mut(), 0)?;
+    /// Fills the levels below the top one of a `D3DFMT_A8R8G8B8` `texture`
+    /// with a box filtered mip chain of `top`, the tightly packed RGBA8
+    /// contents of its top level.
+    unsafe fn write_mips(
+        device: &D,
+        texture: &D::Texture,
+        levels: u32,
+        top: &[u8],
+        width: usize,
+        height: usize,
+    ) -> Result<()> {
+        let (mut mip, mut w, mut h) = (None, width, height);
+        for level in 1..levels {
+            let data = pixels::downsample_rgba(mip.as_deref().unwrap_or(top), w, h);
+            (w, h) = ((w / 2).max(1), (h / 2).max(1));
+            Self::write_texture(device, texture, level, None, 0, (w * 4, h), |dst, pitch| {
+                pixels::rgba_to_bgra(&data, w * 4, dst, pitch, w, h)
+            })?;
+            mip = Some(data);
+        }
+        Ok(())
+    }
 
-        let bits = locked_rect.pBits as *mut u8;
-        let pitch = locked_rect.Pitch as usize;
-        let height = texture.height as usize;
-        let width = texture.width as usize;
+    /// Reads the top level of a `D3DFMT_A8R8G8B8` `texture` back as tightly
+    /// packed RGBA8.
+    unsafe fn read_rgba8_level(
+        device: &D,
+        texture: &D::Texture,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![0; width * height * 4];
+        let locked_rect = device.lock_rect(texture, 0, None, D3DLOCK_READONLY)?;
+        let src = slice::from_raw_parts(
+            locked_rect.bits as *const u8,
+            locked_rect.pitch * (height - 1) + width * 4,
+        );
+        // Swapping red and blue again turns BGRA back into RGBA.
+        pixels::rgba_to_bgra(src, locked_rect.pitch, &mut data, width * 4, width, height);
+        device.unlock_rect(texture, 0)?;
+        Ok(data)
+    }
 
-        for y in 0..height {
-            let d3d9_memory = bits.add(pitch * y);
-            let pixels = texture.data.as_ptr();
-            let pixels = pixels.add((width * 4) * y);
-            std::ptr::copy(pixels, d3d9_memory, width * 4);
+    /// Creates the magenta and black checkerboard drawn in place of missing
+    /// textures under [`InvalidTexturePolicy::Placeholder`].
+    unsafe fn create_placeholder_texture(device: &D) -> Result<D::Texture> {
+        let data: Vec<u8> = (0..PLACEHOLDER_SIZE * PLACEHOLDER_SIZE)
+            .flat_map(|i| {
+                let (x, y) = (i % PLACEHOLDER_SIZE, i / PLACEHOLDER_SIZE);
+                if (x + y) % 2 == 0 {
+                    [0xFF, 0x00, 0xFF, 0xFF]
+                } else {
+                    [0x00, 0x00, 0x00, 0xFF]
+                }
+            })
+            .collect();
+        Self::create_managed_rgba8_texture(device, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, &data, false)
+    }
+
+    unsafe fn create_font_atlas_texture(
+        device: &D,
+        width: u32,
+        height: u32,
+        format: u32,
+    ) -> Result<D::Texture> {
+        device
+            .create_texture(width, height, 1, D3DUSAGE_DYNAMIC, format, D3DPOOL_DEFAULT)
+            .map_err(|source| RendererError::TextureCreation { width, height, format, source })
+    }
+
+    /// Locks `level` of `texture`, either the whole level or just `rect`, and
+    /// hands the locked memory and its pitch to `write`.
+    ///
+    /// `extent` is the size in bytes of one row and the number of rows of the
+    /// area `write` may touch.
+    unsafe fn write_texture(
+        device: &D,
+        texture: &D::Texture,
+        level: u32,
+        rect: Option<&Rect>,
+        flags: u32,
+        (row_len, rows): (usize, usize),
+        write: impl FnOnce(&mut [u8], usize),
+    ) -> Result<()> {
+        if row_len == 0 || rows == 0 {
+            return Ok(());
         }
+        let locked_rect = device.lock_rect(texture, level, rect, flags)?;
+        let dst = slice::from_raw_parts_mut(
+            locked_rect.bits as *mut u8,
+            locked_rect.pitch * (rows - 1) + row_len,
+        );
+        write(dst, locked_rect.pitch);
+        Ok(device.unlock_rect(texture, level)?)
+    }
+}
 
-        result_texture.UnlockRect(0)?;
-        fonts.tex_id = TextureId::from(FONT_TEX_ID);
-        Ok(result_texture)
+/// The viewport covering the framebuffer of `draw_data`.
+fn viewport(draw_data: &DrawData) -> Viewport {
+    Viewport {
+        x: 0,
+        y: 0,
+        width: (draw_data.display_size[0] * draw_data.framebuffer_scale[0]) as u32,
+        height: (draw_data.display_size[1] * draw_data.framebuffer_scale[1]) as u32,
+        min_z: 0.0,
+        max_z: 1.0,
     }
 }
 
-struct StateBackup(IDirect3DStateBlock9);
+/// The columns of `m`, which `m4x4` in the built-in vertex shader takes as
+/// its rows.
+fn transposed(m: &Matrix4x4) -> [[f32; 4]; 4] {
+    [
+        [m.M11, m.M21, m.M31, m.M41],
+        [m.M12, m.M22, m.M32, m.M42],
+        [m.M13, m.M23, m.M33, m.M43],
+        [m.M14, m.M24, m.M34, m.M44],
+    ]
+}
+
+/// What [`Renderer::render`] hands down while drawing one frame.
+struct FrameState<D: Device> {
+    /// The unregistered textures met, with the command they were met in.
+    missing_textures: Vec<(TextureId, CommandIndex)>,
+    /// The callbacks added since the last frame, by id.
+    draw_callbacks: HashMap<usize, DrawCallback<D>>,
+    /// The pixel shaders pushed in the current draw list, innermost last.
+    pixel_shaders: Vec<PixelShaderScope<D>>,
+}
+
+/// The texture and scissor rect last set on the device while rendering.
+#[derive(Default)]
+struct BoundState {
+    texture_id: Option<TextureId>,
+    scissor: Option<Rect>,
+}
+
+/// Holds the state block the device state is restored from after rendering.
+///
+/// [`restore`](Self::restore) reports whether applying the block failed,
+/// dropping the backup without calling it restores the state on a best effort
+/// basis, which only happens when rendering unwinds.
+struct StateBackup<'d, D: Device> {
+    device: &'d D,
+    block: Option<D::StateBlock>,
+}
+
+impl<'d, D: Device> StateBackup<'d, D> {
+    unsafe fn backup(device: &'d D) -> Result<Self> {
+        let block = device.create_state_block(D3DSBT_ALL)?;
+        Ok(StateBackup { device, block: Some(block) })
+    }
+
+    unsafe fn capture(device: &'d D, block: &D::StateBlock) -> Result<Self> {
+        device.capture_state_block(block)?;
+        Ok(StateBackup { device, block: Some(block.clone()) })
+    }
 
-impl StateBackup {
-    unsafe fn backup(device: &IDirect3DDevice9) -> Result<Self> {
-        device.CreateStateBlock(D3DSBT_ALL).map(StateBackup)
+    unsafe fn restore(mut self) -> Result<()> {
+        match self.block.take() {
+            Some(block) => {
+                self.device.apply_state_block(&block).map_err(RendererError::StateRestore)
+            },
+            None => Ok(()),
+        }
     }
 }
 
-impl Drop for StateBackup {
+impl<D: Device> Drop for StateBackup<'_, D> {
     #[inline]
     fn drop(&mut self) {
-        unsafe { self.0.Apply().expect("applying state backup failed") };
+        if let Some(block) = self.block.take() {
+            // There is no way to report the error from here.
+            let _ = unsafe { self.device.apply_state_block(&block) };
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use imgui::Ui;
+
+    use super::*;
+    use crate::recording::{Call, RecordingDevice, ResourceId};
+
+    /// An imgui context and a renderer drawing its 800x600 display into a
+    /// [`RecordingDevice`].
+    pub(crate) struct Fixture {
+        pub(crate) ctx: Context,
+        pub(crate) device: RecordingDevice,
+        pub(crate) renderer: Renderer<RecordingDevice>,
+    }
+
+    impl Fixture {
+        pub(crate) fn new(options: RendererOptions) -> Self {
+            Self::with_device(RecordingDevice::new(), options)
+        }
+
+        /// Like [`new`](Self::new) but drawing into `device`, which may be
+        /// set up beforehand.
+        pub(crate) fn with_device(device: RecordingDevice, options: RendererOptions) -> Self {
+            let mut ctx = Context::create();
+            ctx.set_ini_filename(None);
+            ctx.io_mut().display_size = [800.0, 600.0];
+            let renderer =
+                unsafe { Renderer::new_with_options(&mut ctx, device.clone(), options).unwrap() };
+            Fixture { ctx, device, renderer }
+        }
+
+        /// Builds a frame with `build` and renders it, returning the result
+        /// and the calls made while rendering.
+        ///
+        /// imgui skips drawing new windows in their first frame, and
+        /// `DrawData::draw_lists` is UB on empty draw data, so a warm-up frame
+        /// is built without being rendered.
+        pub(crate) fn render(
+            &mut self,
+            mut build: impl FnMut(&Ui, &mut Renderer<RecordingDevice>),
+        ) -> (Result<()>, Vec<Call>) {
+            let ui = self.ctx.new_frame();
+            build(ui, &mut self.renderer);
+            self.ctx.render();
+
+            let ui = self.ctx.new_frame();
+            build(ui, &mut self.renderer);
+            let draw_data = self.ctx.render();
+            self.device.clear_calls();
+            let result = self.renderer.render(draw_data);
+            (result, self.device.calls())
+        }
+    }
+
+    pub(crate) fn hello_window(ui: &Ui, _: &mut Renderer<RecordingDevice>) {
+        ui.window("hello").build(|| ui.text("Hello world!"));
+    }
+
+    fn position(calls: &[Call], f: impl Fn(&Call) -> bool) -> usize {
+        calls.iter().position(f).expect("call not made")
+    }
+
+    #[test]
+    fn render_sets_up_draws_and_restores() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let font_tex = fixture.renderer.font_tex.unwrap();
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+
+        assert_eq!(calls[0], Call::TestCooperativeLevel);
+        let Call::CreateStateBlock { id: block, ty: D3DSBT_ALL } = calls[1] else {
+            panic!("expected a D3DSBT_ALL state block, got {:?}", calls[1]);
+        };
+        assert_eq!(calls.last(), Some(&Call::ApplyStateBlock(block)));
+
+        let viewport = Viewport { x: 0, y: 0, width: 800, height: 600, min_z: 0.0, max_z: 1.0 };
+        let first_draw = position(&calls, |c| matches!(c, Call::DrawIndexedPrimitive { .. }));
+        let setup = &calls[..first_draw];
+        for call in [
+            Call::SetViewport(viewport),
+            Call::SetPixelShader(None),
+            Call::SetVertexShader(None),
+            Call::SetRenderState(D3DRS_ALPHABLENDENABLE, TRUE),
+            Call::SetRenderState(D3DRS_SCISSORTESTENABLE, TRUE),
+            Call::SetRenderState(D3DRS_CULLMODE, D3DCULL_NONE),
+            Call::SetTransform(D3DTS_WORLD, MAT_IDENTITY),
+            Call::SetFVF(D3DFVF_CUSTOMVERTEX),
+        ] {
+            assert!(setup.contains(&call), "{:?} not set before drawing", call);
+        }
+        let unlocked = position(&calls, |c| matches!(c, Call::UnlockIndexBuffer(_)));
+        assert!(unlocked < first_draw);
+
+        let texture = position(&calls, |c| *c == Call::SetTexture(0, Some(font_tex)));
+        let scissor = position(&calls, |c| matches!(c, Call::SetScissorRect(_)));
+        assert!(texture < scissor && scissor < first_draw);
+        // Nothing but the restore follows the last draw.
+        assert!(matches!(calls[calls.len() - 2], Call::DrawIndexedPrimitive { .. }));
+    }
+
+    #[test]
+    fn draw_lists_beyond_16_bit_indices_are_split() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let (result, calls) = fixture.render(|ui, _| {
+            ui.window("big").build(|| {
+                let draw_list = ui.get_window_draw_list();
+                for i in 0..20000 {
+                    let x = (i % 100) as f32;
+                    draw_list
+                        .add_rect([x, 0.0], [x + 1.0, 1.0], [1.0, 0.0, 0.0, 1.0])
+                        .filled(true)
+                        .build();
+                }
+            });
+        });
+        result.unwrap();
+        // The buffers are locked for exactly the vertices and indices of the
+        // frame.
+        let vtx_count = calls
+            .iter()
+            .find_map(|c| match c {
+                Call::LockVertexBuffer { size, .. } => {
+                    Some(size / mem::size_of::<CustomVertex>() as u32)
+                },
+                _ => None,
+            })
+            .unwrap();
+        let idx_count = calls
+            .iter()
+            .find_map(|c| match c {
+                Call::LockIndexBuffer { size, .. } => Some(size / mem::size_of::<DrawIdx>() as u32),
+                _ => None,
+            })
+            .unwrap();
+        assert!(vtx_count > u16::MAX as u32);
+        let draws: Vec<_> = calls
+            .iter()
+            .filter_map(|c| match *c {
+                Call::DrawIndexedPrimitive {
+                    base_vertex_index,
+                    num_vertices,
+                    start_index,
+                    prim_count,
+                    ..
+                } => Some((base_vertex_index, num_vertices, start_index, prim_count)),
+                _ => None,
+            })
+            .collect();
+        assert!(draws.iter().any(|&(base_vertex_index, ..)| base_vertex_index > 0));
+        let mut end = 0;
+        for &(base_vertex_index, num_vertices, start_index, prim_count) in &draws {
+            assert_eq!(start_index, end);
+            assert!(base_vertex_index as u32 + num_vertices <= vtx_count);
+            end = start_index + prim_count * 3;
+        }
+        assert_eq!(end, idx_count);
+    }
+
+    /// The texture, scissor rect and draw calls made after the buffers were
+    /// written, as `T`, `S` and `D`.
+    fn draw_sequence(calls: &[Call]) -> String {
+        let written = position(calls, |c| matches!(c, Call::UnlockIndexBuffer(_)));
+        calls[written..]
+            .iter()
+            .filter_map(|c| match c {
+                Call::SetTexture(0, _) => Some('T'),
+                Call::SetScissorRect(_) => Some('S'),
+                Call::DrawIndexedPrimitive { .. } => Some('D'),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn prim_counts(calls: &[Call]) -> Vec<u32> {
+        calls
+            .iter()
+            .filter_map(|c| match c {
+                Call::DrawIndexedPrimitive { prim_count, .. } => Some(*prim_count),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds a `ResetRenderState` command to the current window's draw list,
+    /// which imgui-rs has no safe way to do.
+    fn add_reset_render_state() {
+        type RawCallback =
+            unsafe extern "C" fn(*const imgui::sys::ImDrawList, *const imgui::sys::ImDrawCmd);
+        unsafe {
+            // ImDrawCallback_ResetRenderState
+            let reset = mem::transmute::<isize, RawCallback>(-1);
+            imgui::sys::ImDrawList_AddCallback(
+                imgui::sys::igGetWindowDrawList(),
+                Some(reset),
+                std::ptr::null_mut(),
+            );
+        }
+    }
+
+    #[test]
+    fn contiguous_commands_are_drawn_at_once() {
+        // Both unregistered ids are drawn with the placeholder, so the two
+        // commands end up with the same texture and clip rect.
+        let mut fixture = Fixture::new(RendererOptions {
+            invalid_texture: InvalidTexturePolicy::Placeholder,
+            ..RendererOptions::default()
+        });
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(TextureId::from(1000), [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_image(TextureId::from(1001), [10.0, 0.0], [20.0, 10.0]).build();
+        });
+        result.unwrap();
+        assert_eq!(draw_sequence(&calls), "TSD");
+        assert_eq!(prim_counts(&calls), [4]);
+    }
+
+    #[test]
+    fn texture_and_clip_changes_split_batches() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let a = fixture.renderer.create_texture_rgba8(1, 1, &[0; 4]).unwrap();
+        let b = fixture.renderer.create_texture_rgba8(1, 1, &[0; 4]).unwrap();
+        let (a_id, b_id) = (a.id(), b.id());
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(a_id, [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_image(b_id, [10.0, 0.0], [20.0, 10.0]).build();
+            draw_list.with_clip_rect([0.0, 0.0], [5.0, 5.0], || {
+                draw_list.add_image(b_id, [0.0, 0.0], [10.0, 10.0]).build();
+            });
+        });
+        result.unwrap();
+        // Neither the unchanged scissor rect nor the unchanged texture are set
+        // again.
+        assert_eq!(draw_sequence(&calls), "TSDTDSD");
+        assert_eq!(prim_counts(&calls), [2, 2, 2]);
+    }
+
+    #[test]
+    fn index_gaps_split_batches() {
+        let mut fixture = Fixture::new(RendererOptions {
+            invalid_texture: InvalidTexturePolicy::Skip,
+            ..RendererOptions::default()
+        });
+        let a = fixture.renderer.create_texture_rgba8(1, 1, &[0; 4]).unwrap();
+        let a_id = a.id();
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(a_id, [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_image(TextureId::from(1000), [10.0, 0.0], [20.0, 10.0]).build();
+            draw_list.add_image(a_id, [20.0, 0.0], [30.0, 10.0]).build();
+        });
+        result.unwrap();
+        assert_eq!(draw_sequence(&calls), "TSDD");
+        let starts: Vec<_> = calls
+            .iter()
+            .filter_map(|c| match c {
+                Call::DrawIndexedPrimitive { start_index, .. } => Some(*start_index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(starts, [0, 12]);
+    }
+
+    #[test]
+    fn state_is_set_again_after_reset_and_callbacks() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let (result, calls) = fixture.render(|ui, _| {
+            ui.window("reset").build(|| {
+                ui.text("before");
+                add_reset_render_state();
+                ui.text("after");
+            });
+        });
+        result.unwrap();
+        // The window background, the text before the reset, then the text
+        // after it with texture and scissor rect set again.
+        assert_eq!(draw_sequence(&calls), "TSDSDTSD");
+
+        let (result, calls) = fixture.render(|ui, renderer| {
+            ui.window("callback").build(|| {
+                ui.text("before");
+                renderer.add_draw_callback(&ui.get_window_draw_list(), |_| {});
+                ui.text("after");
+            });
+        });
+        result.unwrap();
+        // The callback gets its own scissor rect, which is replaced again
+        // afterwards.
+        assert_eq!(draw_sequence(&calls), "TSDSDSTSD");
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let renderer = &mut fixture.renderer;
+        let invalid = |result: Result<_>| match result {
+            Err(RendererError::InvalidArgument { what }) => what,
+            _ => panic!("expected InvalidArgument"),
+        };
+        invalid(renderer.create_texture_rgba8(2, 2, &[0; 15]).map(drop));
+        invalid(unsafe { renderer.create_pixel_shader(&[0; 6]) }.map(drop));
+        invalid(unsafe { renderer.create_pixel_shader(&[0; 8]) }.map(drop));
+        let unterminated = [0x00, 0x02, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00];
+        invalid(unsafe { renderer.create_pixel_shader(&unterminated) }.map(drop));
+
+        let handle = renderer.create_texture_rgba8(2, 2, &[0; 16]).unwrap();
+        let rect = Rect { left: 1, top: 0, right: 3, bottom: 1 };
+        invalid(renderer.update_texture(handle.id(), rect, &[0; 8]));
+        let rect = Rect { left: 0, top: 0, right: 2, bottom: 1 };
+        invalid(renderer.update_texture(handle.id(), rect, &[0; 7]));
+
+        let texture = *handle.texture();
+        let font = TextureId::from(FONT_TEX_ID);
+        let result = renderer.textures_mut().replace(font, texture);
+        assert!(matches!(result, Err(RendererError::ReservedTextureId(id)) if id == font));
+    }
+
+    #[test]
+    fn missing_textures_are_reported_once_per_hook() {
+        let mut fixture = Fixture::new(RendererOptions {
+            invalid_texture: InvalidTexturePolicy::Skip,
+            ..RendererOptions::default()
+        });
+        let build = |ui: &Ui, _: &mut Renderer<RecordingDevice>| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(TextureId::from(1000), [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_image(TextureId::from(1001), [10.0, 0.0], [20.0, 10.0]).build();
+            draw_list.add_image(TextureId::from(1000), [20.0, 0.0], [30.0, 10.0]).build();
+        };
+        // Ids met before a hook is set are reported once it is.
+        fixture.render(build).0.unwrap();
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let set_hook = |renderer: &mut Renderer<RecordingDevice>| {
+            let reported = reported.clone();
+            renderer.set_diagnostics_hook(move |e| {
+                if let RendererError::InvalidTexture { texture_id, .. } = e {
+                    reported.borrow_mut().push(texture_id.id());
+                }
+            });
+        };
+        set_hook(&mut fixture.renderer);
+        for _ in 0..3 {
+            fixture.render(build).0.unwrap();
+        }
+        assert_eq!(*reported.borrow(), [1000, 1001]);
+
+        set_hook(&mut fixture.renderer);
+        fixture.render(build).0.unwrap();
+        assert_eq!(*reported.borrow(), [1000, 1001, 1000, 1001]);
+    }
+
+    #[test]
+    fn srgb_follows_device_support() {
+        // The R5G6B5 render target cannot be written as sRGB.
+        let mut ctx = Context::create();
+        let device = RecordingDevice::new();
+        device.set_render_target_format(D3DFMT_R5G6B5);
+        device.set_format_usage_supported(D3DUSAGE_QUERY_SRGBWRITE, D3DFMT_R5G6B5, false);
+        let options = RendererOptions { srgb: true, ..RendererOptions::default() };
+        let result = unsafe { Renderer::new_with_options(&mut ctx, device.clone(), options) };
+        assert_eq!(result.err().map(|e| e.code()), Some(D3DERR_NOTAVAILABLE));
+        let query =
+            Call::CheckSurfaceFormat { usage: D3DUSAGE_QUERY_SRGBWRITE, format: D3DFMT_R5G6B5 };
+        assert_eq!(device.calls(), [Call::GetRenderTargetFormat(0), query]);
+        drop(ctx);
+
+        let device = RecordingDevice::new();
+        device.set_format_usage_supported(D3DUSAGE_QUERY_SRGBREAD, D3DFMT_A8, false);
+        let mut fixture = Fixture::with_device(
+            device,
+            RendererOptions {
+                srgb: true,
+                font_atlas: FontAtlasFormat::Alpha8,
+                ..RendererOptions::default()
+            },
+        );
+        let image = fixture.renderer.create_texture_rgba8(1, 1, &[0; 4]).unwrap();
+        let image_id = image.id();
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(image_id, [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_text([20.0, 0.0], [1.0, 1.0, 1.0, 1.0], "text");
+            draw_list.add_image(image_id, [0.0, 20.0], [10.0, 30.0]).build();
+        });
+        result.unwrap();
+        assert!(calls.contains(&Call::SetRenderState(D3DRS_SRGBWRITEENABLE, TRUE)));
+        let srgb_textures = |calls: &[Call]| -> Vec<u32> {
+            calls
+                .iter()
+                .filter_map(|c| match c {
+                    Call::SetSamplerState(0, D3DSAMP_SRGBTEXTURE, value) => Some(*value),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(srgb_textures(&calls), [TRUE, FALSE, TRUE]);
+        let queries = |calls: &[Call]| {
+            calls
+                .iter()
+                .filter(|c| {
+                    matches!(c, Call::CheckTextureFormat { .. } | Call::GetLevelDesc { .. })
+                })
+                .count()
+        };
+        // Both textures were checked when they were created.
+        assert_eq!(queries(&calls), 0);
+
+        // Textures inserted into the registry are checked once they are drawn.
+        let inserted = fixture.renderer.textures_mut().insert(*image.texture());
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_image(inserted, [0.0, 0.0], [10.0, 10.0]).build();
+            draw_list.add_text([20.0, 0.0], [1.0, 1.0, 1.0, 1.0], "text");
+            draw_list.add_image(inserted, [0.0, 20.0], [10.0, 30.0]).build();
+        });
+        result.unwrap();
+        assert_eq!(srgb_textures(&calls), [TRUE, FALSE, TRUE]);
+        assert_eq!(queries(&calls), 2);
+    }
+
+    #[test]
+    fn srgb_converts_vertex_colors() {
+        let mut fixture =
+            Fixture::new(RendererOptions { srgb: true, ..RendererOptions::default() });
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_rect([0.0, 0.0], [5.0, 5.0], [0.5, 0.5, 0.5, 0.5]).filled(true).build();
+        });
+        result.unwrap();
+        let vb = calls
+            .iter()
+            .find_map(|c| match c {
+                Call::SetStreamSource { buffer, .. } => Some(*buffer),
+                _ => None,
+            })
+            .unwrap();
+        let vertices = fixture.device.resource_data(vb, 0).unwrap();
+        let color = &vertices[12..16];
+        assert_eq!(color, [55, 55, 55, 128]);
+    }
+
+    #[test]
+    fn update_texture_rebuilds_mips() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let options = TextureOptions { mipmaps: true, ..TextureOptions::default() };
+        let handle =
+            fixture.renderer.create_texture_rgba8_with_options(4, 2, &[0; 32], &options).unwrap();
+        let texture = *handle.texture();
+
+        let rect = Rect { left: 0, top: 0, right: 2, bottom: 2 };
+        fixture.renderer.update_texture(handle.id(), rect, &[200, 100, 40, 255].repeat(4)).unwrap();
+        let level = |level| fixture.device.resource_data(texture, level).unwrap();
+        assert_eq!(level(1), [40, 100, 200, 255, 0, 0, 0, 0]);
+        assert_eq!(level(2), [20, 50, 100, 128]);
+
+        let rect = Rect { left: 0, top: 0, right: 4, bottom: 2 };
+        fixture.renderer.update_texture(handle.id(), rect, &[1, 2, 3, 4].repeat(8)).unwrap();
+        assert_eq!(level(1), [3, 2, 1, 4].repeat(2));
+        assert_eq!(level(2), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn minimal_state_block_is_recorded_once() {
+        let mut fixture = Fixture::new(RendererOptions {
+            state_backup: StateBackupMode::Minimal,
+            ..RendererOptions::default()
+        });
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert_eq!(calls[1], Call::BeginStateBlock);
+        let block = calls
+            .iter()
+            .find_map(|c| match c {
+                Call::EndStateBlock(block) => Some(*block),
+                _ => None,
+            })
+            .unwrap();
+        assert!(calls.contains(&Call::CaptureStateBlock(block)));
+        assert_eq!(calls.last(), Some(&Call::ApplyStateBlock(block)));
+        assert!(!calls.iter().any(|c| matches!(c, Call::CreateStateBlock { .. })));
+
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert!(!calls.contains(&Call::BeginStateBlock));
+        assert_eq!(calls[1], Call::CaptureStateBlock(block));
+        assert_eq!(calls.last(), Some(&Call::ApplyStateBlock(block)));
+    }
+
+    #[test]
+    fn minimal_state_backup_is_full_with_callbacks() {
+        let mut fixture = Fixture::new(RendererOptions {
+            state_backup: StateBackupMode::Minimal,
+            ..RendererOptions::default()
+        });
+        let (result, calls) = fixture.render(|ui, renderer| {
+            ui.window("callback").build(|| {
+                renderer.add_draw_callback(&ui.get_window_draw_list(), |_| {});
+            });
+        });
+        result.unwrap();
+        let block = calls
+            .iter()
+            .find_map(|c| match c {
+                Call::CreateStateBlock { id, ty: D3DSBT_ALL } => Some(*id),
+                _ => None,
+            })
+            .unwrap();
+        assert!(!calls.iter().any(|c| matches!(c, Call::CaptureStateBlock(_))));
+        assert_eq!(calls.last(), Some(&Call::ApplyStateBlock(block)));
+
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert!(!calls.iter().any(|c| matches!(c, Call::CreateStateBlock { .. })));
+        assert!(calls.iter().any(|c| matches!(c, Call::CaptureStateBlock(_))));
+    }
+
+    #[test]
+    fn no_state_backup_leaves_the_state() {
+        let mut fixture = Fixture::new(RendererOptions {
+            state_backup: StateBackupMode::None,
+            ..RendererOptions::default()
+        });
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert!(!calls.iter().any(|c| matches!(
+            c,
+            Call::CreateStateBlock { .. } | Call::CaptureStateBlock(_) | Call::ApplyStateBlock(_)
+        )));
+    }
+
+    #[test]
+    fn lost_devices_release_and_recreate_device_objects() {
+        let mut fixture = Fixture::new(RendererOptions {
+            state_backup: StateBackupMode::Minimal,
+            ..RendererOptions::default()
+        });
+        fixture.render(hello_window).0.unwrap();
+        for code in [D3DERR_DEVICELOST, D3DERR_DEVICENOTRESET] {
+            fixture.device.set_cooperative_level(code);
+            let (result, calls) = fixture.render(hello_window);
+            assert!(matches!(result, Err(RendererError::DeviceLost(c)) if c == code));
+            assert_eq!(calls, [Call::TestCooperativeLevel]);
+            assert!(fixture.renderer.needs_device_objects());
+        }
+
+        // Without device objects nothing is drawn, even once the device is back.
+        fixture.device.set_cooperative_level(windows_core::HRESULT(0));
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert_eq!(calls, [Call::TestCooperativeLevel]);
+
+        fixture.device.clear_calls();
+        fixture.renderer.create_device_objects(fixture.ctx.fonts()).unwrap();
+        assert!(!fixture.renderer.needs_device_objects());
+        let calls = fixture.device.calls();
+        assert!(calls.iter().any(|c| matches!(c, Call::CreateTexture { .. })));
+        assert!(calls.iter().any(|c| matches!(c, Call::CreateVertexBuffer { .. })));
+        assert!(calls.iter().any(|c| matches!(c, Call::CreateIndexBuffer { .. })));
+
+        let font_tex = fixture.renderer.font_tex.unwrap();
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        assert_eq!(calls[1], Call::BeginStateBlock);
+        assert!(calls.contains(&Call::SetTexture(0, Some(font_tex))));
+        assert!(calls.iter().any(|c| matches!(c, Call::DrawIndexedPrimitive { .. })));
+    }
+
+    #[test]
+    fn texture_samplers_are_applied_and_forgotten() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let font_tex = fixture.renderer.font_tex.unwrap();
+        let font_sampler = SamplerState {
+            min_filter: TextureFilter::Point,
+            address_u: TextureAddress::Clamp,
+            ..SamplerState::default()
+        };
+        let font_id = fixture.ctx.fonts().tex_id;
+        fixture.renderer.set_texture_sampler(font_id, font_sampler);
+        let image_sampler = SamplerState {
+            min_filter: TextureFilter::Anisotropic,
+            address_v: TextureAddress::Border,
+            border_color: 0xFF00_FF00,
+            max_anisotropy: 4,
+            ..SamplerState::default()
+        };
+        let options = TextureOptions { mipmaps: false, sampler: image_sampler };
+        let image = fixture.renderer.create_texture_rgba8_with_options(1, 1, &[0; 4], &options);
+        let image = image.unwrap();
+        let image_id = image.id();
+        let (result, calls) = fixture.render(|ui, _| {
+            let draw_list = ui.get_foreground_draw_list();
+            draw_list.add_text([0.0, 0.0], [1.0, 1.0, 1.0, 1.0], "text");
+            draw_list.add_image(image_id, [0.0, 20.0], [10.0, 30.0]).build();
+        });
+        result.unwrap();
+
+        // The sampler states set after binding each texture.
+        let mut samplers: HashMap<_, HashMap<u32, u32>> = HashMap::new();
+        let mut bound = None;
+        for call in &calls {
+            match call {
+                Call::SetTexture(0, texture) => bound = *texture,
+                Call::SetSamplerState(0, state, value) => {
+                    samplers.entry(bound.unwrap()).or_default().insert(*state, *value);
+                },
+                _ => {},
+            }
+        }
+        let font = &samplers[&font_tex];
+        assert_eq!(font[&D3DSAMP_MINFILTER], D3DTEXF_POINT);
+        assert_eq!(font[&D3DSAMP_MAGFILTER], D3DTEXF_LINEAR);
+        assert_eq!(font[&D3DSAMP_ADDRESSU], D3DTADDRESS_CLAMP);
+        assert_eq!(font[&D3DSAMP_ADDRESSV], D3DTADDRESS_WRAP);
+        let texture = &samplers[image.texture()];
+        assert_eq!(texture[&D3DSAMP_MINFILTER], D3DTEXF_ANISOTROPIC);
+        assert_eq!(texture[&D3DSAMP_ADDRESSU], D3DTADDRESS_WRAP);
+        assert_eq!(texture[&D3DSAMP_ADDRESSV], D3DTADDRESS_BORDER);
+        assert_eq!(texture[&D3DSAMP_BORDERCOLOR], 0xFF00_FF00);
+        assert_eq!(texture[&D3DSAMP_MAXANISOTROPY], 4);
+        assert_eq!(texture[&D3DSAMP_MIPFILTER], D3DTEXF_NONE);
+
+        fixture.renderer.textures_mut().remove(image_id);
+        assert_eq!(fixture.renderer.texture_sampler(image_id), SamplerState::default());
+        assert_eq!(fixture.renderer.texture_sampler(font_id), font_sampler);
+    }
+
+    #[test]
+    fn placeholder_sampler_only_exists_with_the_placeholder() {
+        let placeholder = TextureId::from(PLACEHOLDER_TEX_ID);
+        let fixture = Fixture::new(RendererOptions::default());
+        assert!(fixture.renderer.textures().sampler(placeholder).is_none());
+        drop(fixture);
+        let fixture = Fixture::new(RendererOptions {
+            invalid_texture: InvalidTexturePolicy::Placeholder,
+            ..RendererOptions::default()
+        });
+        let sampler = fixture.renderer.textures().sampler(placeholder).unwrap();
+        assert_eq!(sampler.min_filter, TextureFilter::Point);
+    }
+
+    #[test]
+    fn failed_restores_are_reported_after_draw_errors() {
+        for state_backup in [StateBackupMode::Full, StateBackupMode::Minimal] {
+            let mut fixture =
+                Fixture::new(RendererOptions { state_backup, ..RendererOptions::default() });
+            fixture.device.set_apply_state_block_result(D3DERR_INVALIDCALL);
+            let (result, calls) = fixture.render(hello_window);
+            let Err(RendererError::StateRestore(source)) = result else {
+                panic!("expected StateRestore, got {:?}", result);
+            };
+            assert_eq!(source.code(), D3DERR_INVALIDCALL);
+            assert!(calls.iter().any(|c| matches!(c, Call::DrawIndexedPrimitive { .. })));
+
+            let (result, calls) = fixture.render(|ui, _| {
+                let draw_list = ui.get_foreground_draw_list();
+                draw_list.add_image(TextureId::from(7), [0.0, 0.0], [1.0, 1.0]).build();
+            });
+            assert!(matches!(result, Err(RendererError::InvalidTexture { .. })), "{:?}", result);
+            assert!(matches!(calls.last(), Some(Call::ApplyStateBlock(_))));
+        }
+    }
+
+    /// The pixel shaders bound, by their letter in `names`, constant uploads
+    /// as `c` and draws as `D`, after the buffers were written.
+    fn pixel_shader_sequence(calls: &[Call], names: &[(ResourceId, char)]) -> String {
+        let written = position(calls, |c| matches!(c, Call::UnlockIndexBuffer(_)));
+        calls[written..]
+            .iter()
+            .filter_map(|c| match c {
+                Call::SetPixelShader(Some(shader)) => {
+                    names.iter().find(|(id, _)| id == shader).map(|(_, name)| *name)
+                },
+                Call::SetPixelShaderConstantF { .. } => Some('c'),
+                Call::DrawIndexedPrimitive { .. } => Some('D'),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn pixel_shader_bytecode() -> Vec<u8> {
+        shaders::PIXEL_SHADER.iter().flat_map(|token| token.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn pixel_shader_scopes_nest_and_end_with_their_draw_list() {
+        let mut fixture = Fixture::new(RendererOptions {
+            pipeline: Pipeline::Programmable,
+            ..RendererOptions::default()
+        });
+        let builtin = fixture.renderer.shaders.as_ref().unwrap().pixel;
+        let bytecode = pixel_shader_bytecode();
+        let outer = unsafe { fixture.renderer.create_pixel_shader(&bytecode) }.unwrap();
+        let inner = unsafe { fixture.renderer.create_pixel_shader(&bytecode) }.unwrap();
+        let (result, calls) = fixture.render(|ui, renderer| {
+            ui.window("shaded").build(|| {
+                let draw_list = ui.get_window_draw_list();
+                renderer.push_pixel_shader(&draw_list, &outer, &[[1.0, 0.5, 0.0, 1.0]]);
+                ui.text("outer");
+                renderer.push_pixel_shader(&draw_list, &inner, &[]);
+                ui.text("inner");
+                renderer.pop_pixel_shader(&draw_list);
+                ui.text("outer again");
+                renderer.pop_pixel_shader(&draw_list);
+                ui.text("builtin");
+                // Never popped, which must not affect the next window.
+                renderer.push_pixel_shader(&draw_list, &inner, &[]);
+                ui.text("inner again");
+            });
+            ui.window("plain").build(|| ui.text("builtin"));
+        });
+        result.unwrap();
+        let names = [(builtin, 'B'), (outer, 'O'), (inner, 'I')];
+        assert_eq!(pixel_shader_sequence(&calls, &names), "DOcDIDOcDBDIDBDD");
+        assert!(calls.contains(&Call::SetPixelShaderConstantF {
+            start_register: 0,
+            constants: vec![[1.0, 0.5, 0.0, 1.0]],
+        }));
+    }
+
+    #[test]
+    fn pixel_shader_scopes_survive_reset_render_state() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let shader = unsafe { fixture.renderer.create_pixel_shader(&pixel_shader_bytecode()) };
+        let shader = shader.unwrap();
+        let (result, calls) = fixture.render(|ui, renderer| {
+            ui.window("shaded").build(|| {
+                let draw_list = ui.get_window_draw_list();
+                renderer.push_pixel_shader(&draw_list, &shader, &[[1.0; 4]]);
+                ui.text("shaded");
+                add_reset_render_state();
+                ui.text("still shaded");
+                renderer.pop_pixel_shader(&draw_list);
+                ui.text("fixed function");
+            });
+        });
+        result.unwrap();
+        let sequence: String = calls
+            .iter()
+            .filter_map(|c| match c {
+                Call::SetPixelShader(Some(_)) => Some('P'),
+                Call::SetPixelShader(None) => Some('F'),
+                Call::SetPixelShaderConstantF { .. } => Some('c'),
+                Call::DrawIndexedPrimitive { .. } => Some('D'),
+                _ => None,
+            })
+            .collect();
+        // The state setup, then the reset rebinding the shader in between.
+        assert_eq!(sequence, "FDPcDFPcDFD");
+    }
+
+    #[test]
+    fn minimal_state_backup_is_full_with_pixel_shaders() {
+        let mut fixture = Fixture::new(RendererOptions {
+            state_backup: StateBackupMode::Minimal,
+            ..RendererOptions::default()
+        });
+        let shader = unsafe { fixture.renderer.create_pixel_shader(&pixel_shader_bytecode()) };
+        let shader = shader.unwrap();
+        let (result, calls) = fixture.render(|ui, renderer| {
+            ui.window("shaded").build(|| {
+                let draw_list = ui.get_window_draw_list();
+                renderer.push_pixel_shader(&draw_list, &shader, &[[1.0; 4]]);
+                ui.text("shaded");
+            });
+        });
+        result.unwrap();
+        assert!(calls.iter().any(|c| matches!(c, Call::CreateStateBlock { ty: D3DSBT_ALL, .. })));
+        assert!(!calls.iter().any(|c| matches!(c, Call::CaptureStateBlock(_))));
+    }
+
+    #[test]
+    fn programmable_pipeline_binds_the_builtin_shaders() {
+        let mut fixture = Fixture::new(RendererOptions {
+            pipeline: Pipeline::Programmable,
+            ..RendererOptions::default()
+        });
+        let BuiltinShaders { vertex, pixel } = *fixture.renderer.shaders.as_ref().unwrap();
+        let created = fixture.device.calls();
+        assert!(created.contains(&Call::CreateVertexShader {
+            id: vertex,
+            function: shaders::VERTEX_SHADER.to_vec(),
+        }));
+        assert!(created.contains(&Call::CreatePixelShader {
+            id: pixel,
+            function: shaders::PIXEL_SHADER.to_vec()
+        }));
+
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        let first_draw = position(&calls, |c| matches!(c, Call::DrawIndexedPrimitive { .. }));
+        let setup = &calls[..first_draw];
+        assert!(setup.contains(&Call::SetVertexShader(Some(vertex))));
+        assert!(setup.contains(&Call::SetPixelShader(Some(pixel))));
+        assert!(!calls.iter().any(|c| matches!(c, Call::SetTransform(..))));
+        let projection = setup
+            .iter()
+            .find_map(|c| match c {
+                Call::SetVertexShaderConstantF { start_register: 0, constants } => Some(constants),
+                _ => None,
+            })
+            .unwrap();
+        // The transposed projection of the 800x600 display, rows first.
+        assert_eq!(projection.len(), 4);
+        assert_eq!(projection[0], [2.0 / 800.0, 0.0, 0.0, 801.0 / -800.0]);
+        assert_eq!(projection[1], [0.0, 2.0 / -600.0, 0.0, 601.0 / 600.0]);
+        assert_eq!(projection[3], [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn minimal_state_block_records_the_shader_constants() {
+        let mut fixture = Fixture::new(RendererOptions {
+            pipeline: Pipeline::Programmable,
+            state_backup: StateBackupMode::Minimal,
+            ..RendererOptions::default()
+        });
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+        let begin = position(&calls, |c| *c == Call::BeginStateBlock);
+        let end = position(&calls, |c| matches!(c, Call::EndStateBlock(_)));
+        let recorded = &calls[begin..end];
+        assert!(recorded.iter().any(|c| matches!(
+            c,
+            Call::SetVertexShaderConstantF { start_register: 0, constants } if constants.len() == 4
+        )));
+        assert!(recorded.iter().any(|c| matches!(c, Call::SetVertexShader(Some(_)))));
+        assert!(recorded.iter().any(|c| matches!(c, Call::SetPixelShader(Some(_)))));
+        assert!(!recorded.iter().any(|c| matches!(c, Call::SetTransform(..))));
+    }
+
+    #[test]
+    fn programmable_alpha8_font_atlas_skips_a8() {
+        for (pipeline, format) in
+            [(Pipeline::FixedFunction, D3DFMT_A8), (Pipeline::Programmable, D3DFMT_A8L8)]
+        {
+            let fixture = Fixture::new(RendererOptions {
+                font_atlas: FontAtlasFormat::Alpha8,
+                pipeline,
+                ..RendererOptions::default()
+            });
+            let font_tex = fixture.renderer.font_tex.unwrap();
+            let calls = fixture.device.calls();
+            let created = calls
+                .iter()
+                .find_map(|c| match c {
+                    Call::CreateTexture { id, format, .. } if *id == font_tex => Some(*format),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(created, format, "{:?}", pipeline);
+            assert_eq!(fixture.renderer.font_format, format);
+        }
     }
 }
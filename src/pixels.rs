@@ -0,0 +1,161 @@
+//! CPU side pixel conversions for texture uploads.
+
+use std::sync::OnceLock;
+
+use crate::device::consts::*;
+
+/// Copies `height` rows of `width` RGBA8 pixels from `src` into `dst`,
+/// swapping the red and blue channels.
+///
+/// `D3DFMT_A8R8G8B8` keeps its channels in BGRA byte order in memory, while
+/// imgui and most image sources hand out RGBA.
+pub(crate) fn rgba_to_bgra(
+    src: &[u8],
+    src_pitch: usize,
+    dst: &mut [u8],
+    dst_pitch: usize,
+    width: usize,
+    height: usize,
+) {
+    for y in 0..height {
+        let src = &src[y * src_pitch..][..width * 4];
+        let dst = &mut dst[y * dst_pitch..][..width * 4];
+        for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+            d.copy_from_slice(&[s[2], s[1], s[0], s[3]]);
+        }
+    }
+}
+
+/// Halves tightly packed RGBA8 pixels with a 2x2 box filter, producing the
+/// next mip level.
+///
+/// Sizes round down but never below one pixel, odd edges reuse their last
+/// row or column.
+pub(crate) fn downsample_rgba(src: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+    let mut dst = Vec::with_capacity(next_width * next_height * 4);
+    for y in 0..next_height {
+        let rows = [(2 * y).min(height - 1), (2 * y + 1).min(height - 1)];
+        for x in 0..next_width {
+            let columns = [(2 * x).min(width - 1), (2 * x + 1).min(width - 1)];
+            for c in 0..4 {
+                let sum: u32 = rows
+                    .iter()
+                    .flat_map(|&y| {
+                        columns.iter().map(move |&x| src[(y * width + x) * 4 + c] as u32)
+                    })
+                    .sum();
+                dst.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    dst
+}
+
+/// The size of one texel of an uncompressed format.
+pub(crate) fn bytes_per_pixel(format: u32) -> usize {
+    match format {
+        D3DFMT_A8 | D3DFMT_L8 => 1,
+        D3DFMT_A8L8 | D3DFMT_R5G6B5 | D3DFMT_X1R5G5B5 | D3DFMT_A1R5G5B5 | D3DFMT_A4R4G4B4 => 2,
+        _ => 4,
+    }
+}
+
+/// The edge length in pixels and the size in bytes of one block of `format`.
+///
+/// DXTn formats are made of 4x4 blocks, every other format of single texels.
+pub(crate) fn block_layout(format: u32) -> (u32, usize) {
+    match format {
+        D3DFMT_DXT1 => (4, 8),
+        D3DFMT_DXT2 | D3DFMT_DXT3 | D3DFMT_DXT4 | D3DFMT_DXT5 => (4, 16),
+        _ => (1, bytes_per_pixel(format)),
+    }
+}
+
+/// Returns the size in bytes of one row of blocks and the number of such rows
+/// in a surface of `format`.
+pub(crate) fn surface_layout(format: u32, width: u32, height: u32) -> (usize, usize) {
+    let (block_dim, block_bytes) = block_layout(format);
+    (width.div_ceil(block_dim) as usize * block_bytes, height.div_ceil(block_dim) as usize)
+}
+
+/// Copies `rows` rows of `row_len` bytes between buffers with different
+/// pitches.
+pub(crate) fn copy_rows(
+    src: &[u8],
+    src_pitch: usize,
+    dst: &mut [u8],
+    dst_pitch: usize,
+    row_len: usize,
+    rows: usize,
+) {
+    for y in 0..rows {
+        dst[y * dst_pitch..][..row_len].copy_from_slice(&src[y * src_pitch..][..row_len]);
+    }
+}
+
+/// Copies `height` rows of `width` alpha values from `src` into `dst` as
+/// white texels of `format`, which is one of `D3DFMT_A8`, `D3DFMT_A8L8` or
+/// `D3DFMT_A8R8G8B8`.
+pub(crate) fn alpha8_to(
+    format: u32,
+    src: &[u8],
+    src_pitch: usize,
+    dst: &mut [u8],
+    dst_pitch: usize,
+    width: usize,
+    height: usize,
+) {
+    let bpp = bytes_per_pixel(format);
+    for y in 0..height {
+        let src = &src[y * src_pitch..][..width];
+        let dst = &mut dst[y * dst_pitch..][..width * bpp];
+        for (&a, d) in src.iter().zip(dst.chunks_exact_mut(bpp)) {
+            d.fill(0xFF);
+            d[bpp - 1] = a;
+        }
+    }
+}
+
+/// Converts the red, green and blue channels of an 8-bit RGBA color from
+/// sRGB to linear, keeping alpha as is.
+pub(crate) fn srgb_to_linear(color: [u8; 4]) -> [u8; 4] {
+    static TABLE: OnceLock<[u8; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0; 256];
+        for (c, linear) in table.iter_mut().enumerate() {
+            let c = c as f32 / 255.0;
+            let l = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+            *linear = (l * 255.0).round() as u8;
+        }
+        table
+    });
+    [table[color[0] as usize], table[color[1] as usize], table[color[2] as usize], color[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_to_bgra_swaps_red_and_blue() {
+        let src = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut dst = [0; 8];
+        rgba_to_bgra(&src, 8, &mut dst, 8, 2, 1);
+        assert_eq!(dst, [3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn srgb_to_linear_keeps_endpoints_and_alpha() {
+        assert_eq!(srgb_to_linear([0, 255, 128, 77]), [0, 255, 55, 77]);
+        assert_eq!(srgb_to_linear([10, 188, 254, 0]), [1, 128, 253, 0]);
+    }
+
+    #[test]
+    fn rgba_to_bgra_keeps_row_padding() {
+        let src = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut dst = [0xAA; 12];
+        rgba_to_bgra(&src, 4, &mut dst, 6, 1, 2);
+        assert_eq!(dst, [3, 2, 1, 4, 0xAA, 0xAA, 7, 6, 5, 8, 0xAA, 0xAA]);
+    }
+}
@@ -0,0 +1,684 @@
+//! An in-memory [`Device`] that records every call made against it.
+//!
+//! [`RecordingDevice`] needs no GPU and works on every platform, which makes
+//! it possible to assert on the exact call sequence a [`DrawData`] produces.
+//! The device is cheaply cloneable and all clones share the same log, so a
+//! test can hand one clone to the [`Renderer`] and inspect another.
+//!
+//! [`DrawData`]: imgui::DrawData
+//! [`Renderer`]: crate::Renderer
+
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::slice;
+
+use windows_core::{Result, HRESULT};
+use windows_numerics::Matrix4x4;
+
+use crate::device::consts::*;
+use crate::device::{Device, LockedRect, Rect, SurfaceDesc, Viewport};
+use crate::pixels;
+
+/// Identifies a resource created through a [`RecordingDevice`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(u32);
+
+impl ResourceId {
+    /// The index of this resource in creation order.
+    #[inline]
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+/// A single call made against a [`RecordingDevice`].
+#[allow(missing_docs)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    TestCooperativeLevel,
+    CheckTextureFormat {
+        usage: u32,
+        format: u32,
+    },
+    CheckSurfaceFormat {
+        usage: u32,
+        format: u32,
+    },
+    GetRenderTargetFormat(u32),
+    CreateVertexBuffer {
+        id: ResourceId,
+        length: u32,
+        usage: u32,
+        fvf: u32,
+        pool: u32,
+    },
+    CreateIndexBuffer {
+        id: ResourceId,
+        length: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    },
+    CreateTexture {
+        id: ResourceId,
+        width: u32,
+        height: u32,
+        levels: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    },
+    CreateVertexShader {
+        id: ResourceId,
+        function: Vec<u32>,
+    },
+    CreatePixelShader {
+        id: ResourceId,
+        function: Vec<u32>,
+    },
+    CreateStateBlock {
+        id: ResourceId,
+        ty: u32,
+    },
+    BeginStateBlock,
+    EndStateBlock(ResourceId),
+    LockVertexBuffer {
+        buffer: ResourceId,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    },
+    UnlockVertexBuffer(ResourceId),
+    LockIndexBuffer {
+        buffer: ResourceId,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    },
+    UnlockIndexBuffer(ResourceId),
+    GetLevelCount(ResourceId),
+    GetLevelDesc {
+        texture: ResourceId,
+        level: u32,
+    },
+    LockRect {
+        texture: ResourceId,
+        level: u32,
+        rect: Option<Rect>,
+        flags: u32,
+    },
+    UnlockRect {
+        texture: ResourceId,
+        level: u32,
+    },
+    CaptureStateBlock(ResourceId),
+    ApplyStateBlock(ResourceId),
+    SetViewport(Viewport),
+    SetPixelShader(Option<ResourceId>),
+    SetVertexShader(Option<ResourceId>),
+    SetVertexShaderConstantF {
+        start_register: u32,
+        constants: Vec<[f32; 4]>,
+    },
+    SetPixelShaderConstantF {
+        start_register: u32,
+        constants: Vec<[f32; 4]>,
+    },
+    SetRenderState(u32, u32),
+    SetTextureStageState(u32, u32, u32),
+    SetSamplerState(u32, u32, u32),
+    SetTransform(u32, Matrix4x4),
+    SetTexture(u32, Option<ResourceId>),
+    SetScissorRect(Rect),
+    SetStreamSource {
+        stream: u32,
+        buffer: ResourceId,
+        offset: u32,
+        stride: u32,
+    },
+    SetIndices(ResourceId),
+    SetFVF(u32),
+    DrawIndexedPrimitive {
+        primitive_type: u32,
+        base_vertex_index: i32,
+        min_vertex_index: u32,
+        num_vertices: u32,
+        start_index: u32,
+        prim_count: u32,
+    },
+}
+
+/// The backing memory of one buffer or texture level.
+///
+/// Stored as `u32`s so the pointers handed out are suitably aligned for
+/// vertices and indices.
+struct Surface {
+    pitch: usize,
+    rows: usize,
+    data: Vec<u32>,
+}
+
+impl Surface {
+    fn new(pitch: usize, rows: usize) -> Self {
+        Surface { pitch, rows, data: vec![0; (pitch * rows).div_ceil(4)] }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data.as_ptr().cast(), self.pitch * self.rows) }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_mut_ptr().cast()
+    }
+}
+
+struct Resource {
+    desc: SurfaceDesc,
+    surfaces: Vec<Surface>,
+}
+
+#[derive(Default)]
+struct State {
+    calls: Vec<Call>,
+    resources: Vec<Resource>,
+    cooperative_level: HRESULT,
+    apply_state_block_result: HRESULT,
+    /// Usage flags and the format they are unsupported for.
+    unsupported_formats: Vec<(u32, u32)>,
+    /// The format of the render target, `D3DFMT_X8R8G8B8` unless set.
+    render_target_format: Option<u32>,
+}
+
+/// A [`Device`] that records its calls and keeps resources in memory.
+#[derive(Clone, Default)]
+pub struct RecordingDevice {
+    state: Rc<RefCell<State>>,
+}
+
+impl RecordingDevice {
+    /// Creates a new device with an empty call log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of every call recorded so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.borrow().calls.clone()
+    }
+
+    /// Clears the call log, keeping all created resources alive.
+    pub fn clear_calls(&self) {
+        self.state.borrow_mut().calls.clear();
+    }
+
+    /// Sets the result of subsequent `TestCooperativeLevel` calls, for example
+    /// [`D3DERR_DEVICELOST`] to simulate a lost device.
+    pub fn set_cooperative_level(&self, level: HRESULT) {
+        self.state.borrow_mut().cooperative_level = level;
+    }
+
+    /// Sets the result of subsequent `IDirect3DStateBlock9::Apply` calls, to
+    /// simulate failing to restore the device state.
+    pub fn set_apply_state_block_result(&self, result: HRESULT) {
+        self.state.borrow_mut().apply_state_block_result = result;
+    }
+
+    /// Sets the format subsequent `GetRenderTarget` calls report, which is
+    /// `D3DFMT_X8R8G8B8` by default.
+    pub fn set_render_target_format(&self, format: u32) {
+        self.state.borrow_mut().render_target_format = Some(format);
+    }
+
+    /// Sets whether subsequent `CheckDeviceFormat` calls report textures of
+    /// `format` as supported, which all formats are by default.
+    pub fn set_format_supported(&self, format: u32, supported: bool) {
+        self.set_format_usage_supported(0, format, supported);
+    }
+
+    /// Sets whether subsequent `CheckDeviceFormat` calls report textures and
+    /// surfaces of `format` as supported for the `usage` flags, for example
+    /// [`D3DUSAGE_QUERY_SRGBREAD`]. Queries including further flags are
+    /// affected as well.
+    pub fn set_format_usage_supported(&self, usage: u32, format: u32, supported: bool) {
+        let unsupported = &mut self.state.borrow_mut().unsupported_formats;
+        unsupported.retain(|&entry| entry != (usage, format));
+        if !supported {
+            unsupported.push((usage, format));
+        }
+    }
+
+    /// Returns a copy of the bytes backing a buffer or a texture level.
+    ///
+    /// Rows of texture levels are tightly packed. Returns `None` if the
+    /// resource or level does not exist.
+    pub fn resource_data(&self, id: ResourceId, level: u32) -> Option<Vec<u8>> {
+        let state = self.state.borrow();
+        let surface = state.resources.get(id.0 as usize)?.surfaces.get(level as usize)?;
+        Some(surface.bytes().to_vec())
+    }
+
+    fn record(&self, call: Call) {
+        self.state.borrow_mut().calls.push(call);
+    }
+
+    fn check_format(&self, usage: u32, format: u32) -> Result<()> {
+        let unsupported = &self.state.borrow().unsupported_formats;
+        if unsupported.iter().any(|&(u, f)| f == format && usage & u == u) {
+            return Err(D3DERR_NOTAVAILABLE.into());
+        }
+        Ok(())
+    }
+
+    fn create(
+        &self,
+        desc: SurfaceDesc,
+        surfaces: Vec<Surface>,
+        call: impl FnOnce(ResourceId) -> Call,
+    ) -> ResourceId {
+        let mut state = self.state.borrow_mut();
+        let id = ResourceId(state.resources.len() as u32);
+        state.resources.push(Resource { desc, surfaces });
+        state.calls.push(call(id));
+        id
+    }
+
+    fn lock(&self, id: ResourceId, level: u32, offset: usize) -> Result<*mut c_void> {
+        let mut state = self.state.borrow_mut();
+        let surface = state
+            .resources
+            .get_mut(id.0 as usize)
+            .and_then(|resource| resource.surfaces.get_mut(level as usize))
+            .ok_or(D3DERR_INVALIDCALL)?;
+        if offset > surface.pitch * surface.rows {
+            return Err(D3DERR_INVALIDCALL.into());
+        }
+        Ok(unsafe { surface.as_mut_ptr().add(offset).cast() })
+    }
+}
+
+unsafe impl Device for RecordingDevice {
+    type Texture = ResourceId;
+    type VertexBuffer = ResourceId;
+    type IndexBuffer = ResourceId;
+    type StateBlock = ResourceId;
+    type PixelShader = ResourceId;
+    type VertexShader = ResourceId;
+
+    unsafe fn test_cooperative_level(&self) -> Result<()> {
+        self.record(Call::TestCooperativeLevel);
+        self.state.borrow().cooperative_level.ok()
+    }
+
+    unsafe fn check_texture_format(&self, usage: u32, format: u32) -> Result<()> {
+        self.record(Call::CheckTextureFormat { usage, format });
+        self.check_format(usage, format)
+    }
+
+    unsafe fn check_surface_format(&self, usage: u32, format: u32) -> Result<()> {
+        self.record(Call::CheckSurfaceFormat { usage, format });
+        self.check_format(usage, format)
+    }
+
+    unsafe fn get_render_target_format(&self, index: u32) -> Result<u32> {
+        self.record(Call::GetRenderTargetFormat(index));
+        Ok(self.state.borrow().render_target_format.unwrap_or(D3DFMT_X8R8G8B8))
+    }
+
+    unsafe fn create_vertex_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        fvf: u32,
+        pool: u32,
+    ) -> Result<Self::VertexBuffer> {
+        let desc = SurfaceDesc { usage, pool, width: length, height: 1, ..Default::default() };
+        let surfaces = vec![Surface::new(length as usize, 1)];
+        Ok(self.create(desc, surfaces, |id| Call::CreateVertexBuffer {
+            id,
+            length,
+            usage,
+            fvf,
+            pool,
+        }))
+    }
+
+    unsafe fn create_index_buffer(
+        &self,
+        length: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::IndexBuffer> {
+        let desc = SurfaceDesc { format, usage, pool, width: length, height: 1 };
+        let surfaces = vec![Surface::new(length as usize, 1)];
+        Ok(self.create(desc, surfaces, |id| Call::CreateIndexBuffer {
+            id,
+            length,
+            usage,
+            format,
+            pool,
+        }))
+    }
+
+    unsafe fn create_texture(
+        &self,
+        width: u32,
+        height: u32,
+        levels: u32,
+        usage: u32,
+        format: u32,
+        pool: u32,
+    ) -> Result<Self::Texture> {
+        if width == 0 || height == 0 {
+            return Err(D3DERR_INVALIDCALL.into());
+        }
+        let level_count = match levels {
+            0 => 32 - width.max(height).leading_zeros(),
+            levels => levels,
+        };
+        let surfaces = (0..level_count)
+            .map(|level| {
+                let (pitch, rows) = pixels::surface_layout(
+                    format,
+                    (width >> level).max(1),
+                    (height >> level).max(1),
+                );
+                Surface::new(pitch, rows)
+            })
+            .collect();
+        let desc = SurfaceDesc { format, usage, pool, width, height };
+        Ok(self.create(desc, surfaces, |id| Call::CreateTexture {
+            id,
+            width,
+            height,
+            levels,
+            usage,
+            format,
+            pool,
+        }))
+    }
+
+    unsafe fn create_vertex_shader(&self, function: &[u32]) -> Result<Self::VertexShader> {
+        let function = function.to_vec();
+        Ok(self.create(SurfaceDesc::default(), Vec::new(), |id| Call::CreateVertexShader {
+            id,
+            function,
+        }))
+    }
+
+    unsafe fn create_pixel_shader(&self, function: &[u32]) -> Result<Self::PixelShader> {
+        let function = function.to_vec();
+        Ok(self.create(SurfaceDesc::default(), Vec::new(), |id| Call::CreatePixelShader {
+            id,
+            function,
+        }))
+    }
+
+    unsafe fn create_state_block(&self, ty: u32) -> Result<Self::StateBlock> {
+        Ok(self.create(SurfaceDesc::default(), Vec::new(), |id| Call::CreateStateBlock { id, ty }))
+    }
+
+    unsafe fn begin_state_block(&self) -> Result<()> {
+        self.record(Call::BeginStateBlock);
+        Ok(())
+    }
+
+    unsafe fn end_state_block(&self) -> Result<Self::StateBlock> {
+        Ok(self.create(SurfaceDesc::default(), Vec::new(), Call::EndStateBlock))
+    }
+
+    unsafe fn lock_vertex_buffer(
+        &self,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void> {
+        self.record(Call::LockVertexBuffer { buffer: *buffer, offset, size, flags });
+        self.lock(*buffer, 0, offset as usize)
+    }
+
+    unsafe fn unlock_vertex_buffer(&self, buffer: &Self::VertexBuffer) -> Result<()> {
+        self.record(Call::UnlockVertexBuffer(*buffer));
+        Ok(())
+    }
+
+    unsafe fn lock_index_buffer(
+        &self,
+        buffer: &Self::IndexBuffer,
+        offset: u32,
+        size: u32,
+        flags: u32,
+    ) -> Result<*mut c_void> {
+        self.record(Call::LockIndexBuffer { buffer: *buffer, offset, size, flags });
+        self.lock(*buffer, 0, offset as usize)
+    }
+
+    unsafe fn unlock_index_buffer(&self, buffer: &Self::IndexBuffer) -> Result<()> {
+        self.record(Call::UnlockIndexBuffer(*buffer));
+        Ok(())
+    }
+
+    unsafe fn get_level_count(&self, texture: &Self::Texture) -> u32 {
+        self.record(Call::GetLevelCount(*texture));
+        let state = self.state.borrow();
+        state.resources.get(texture.0 as usize).map_or(0, |resource| resource.surfaces.len() as u32)
+    }
+
+    unsafe fn get_level_desc(&self, texture: &Self::Texture, level: u32) -> Result<SurfaceDesc> {
+        self.record(Call::GetLevelDesc { texture: *texture, level });
+        let state = self.state.borrow();
+        let resource = state.resources.get(texture.0 as usize).ok_or(D3DERR_INVALIDCALL)?;
+        if level as usize >= resource.surfaces.len() {
+            return Err(D3DERR_INVALIDCALL.into());
+        }
+        Ok(SurfaceDesc {
+            width: (resource.desc.width >> level).max(1),
+            height: (resource.desc.height >> level).max(1),
+            ..resource.desc
+        })
+    }
+
+    unsafe fn lock_rect(
+        &self,
+        texture: &Self::Texture,
+        level: u32,
+        rect: Option<&Rect>,
+        flags: u32,
+    ) -> Result<LockedRect> {
+        self.record(Call::LockRect { texture: *texture, level, rect: rect.copied(), flags });
+        let (pitch, offset) = {
+            let state = self.state.borrow();
+            let resource = state.resources.get(texture.0 as usize).ok_or(D3DERR_INVALIDCALL)?;
+            let surface = resource.surfaces.get(level as usize).ok_or(D3DERR_INVALIDCALL)?;
+            let offset = rect.map_or(0, |r| {
+                let (block_dim, block_bytes) = pixels::block_layout(resource.desc.format);
+                (r.top as u32 / block_dim) as usize * surface.pitch
+                    + (r.left as u32 / block_dim) as usize * block_bytes
+            });
+            (surface.pitch, offset)
+        };
+        let bits = self.lock(*texture, level, offset)?;
+        Ok(LockedRect { pitch, bits })
+    }
+
+    unsafe fn unlock_rect(&self, texture: &Self::Texture, level: u32) -> Result<()> {
+        self.record(Call::UnlockRect { texture: *texture, level });
+        Ok(())
+    }
+
+    unsafe fn capture_state_block(&self, block: &Self::StateBlock) -> Result<()> {
+        self.record(Call::CaptureStateBlock(*block));
+        Ok(())
+    }
+
+    unsafe fn apply_state_block(&self, block: &Self::StateBlock) -> Result<()> {
+        self.record(Call::ApplyStateBlock(*block));
+        self.state.borrow().apply_state_block_result.ok()
+    }
+
+    unsafe fn set_viewport(&self, viewport: &Viewport) -> Result<()> {
+        self.record(Call::SetViewport(*viewport));
+        Ok(())
+    }
+
+    unsafe fn set_pixel_shader(&self, shader: Option<&Self::PixelShader>) -> Result<()> {
+        self.record(Call::SetPixelShader(shader.copied()));
+        Ok(())
+    }
+
+    unsafe fn set_vertex_shader(&self, shader: Option<&Self::VertexShader>) -> Result<()> {
+        self.record(Call::SetVertexShader(shader.copied()));
+        Ok(())
+    }
+
+    unsafe fn set_vertex_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()> {
+        self.record(Call::SetVertexShaderConstantF {
+            start_register,
+            constants: constants.to_vec(),
+        });
+        Ok(())
+    }
+
+    unsafe fn set_pixel_shader_constant_f(
+        &self,
+        start_register: u32,
+        constants: &[[f32; 4]],
+    ) -> Result<()> {
+        self.record(Call::SetPixelShaderConstantF {
+            start_register,
+            constants: constants.to_vec(),
+        });
+        Ok(())
+    }
+
+    unsafe fn set_render_state(&self, state: u32, value: u32) -> Result<()> {
+        self.record(Call::SetRenderState(state, value));
+        Ok(())
+    }
+
+    unsafe fn set_texture_stage_state(&self, stage: u32, ty: u32, value: u32) -> Result<()> {
+        self.record(Call::SetTextureStageState(stage, ty, value));
+        Ok(())
+    }
+
+    unsafe fn set_sampler_state(&self, sampler: u32, ty: u32, value: u32) -> Result<()> {
+        self.record(Call::SetSamplerState(sampler, ty, value));
+        Ok(())
+    }
+
+    unsafe fn set_transform(&self, state: u32, matrix: &Matrix4x4) -> Result<()> {
+        self.record(Call::SetTransform(state, *matrix));
+        Ok(())
+    }
+
+    unsafe fn set_texture(&self, stage: u32, texture: Option<&Self::Texture>) -> Result<()> {
+        self.record(Call::SetTexture(stage, texture.copied()));
+        Ok(())
+    }
+
+    unsafe fn set_scissor_rect(&self, rect: &Rect) -> Result<()> {
+        self.record(Call::SetScissorRect(*rect));
+        Ok(())
+    }
+
+    unsafe fn set_stream_source(
+        &self,
+        stream: u32,
+        buffer: &Self::VertexBuffer,
+        offset: u32,
+        stride: u32,
+    ) -> Result<()> {
+        self.record(Call::SetStreamSource { stream, buffer: *buffer, offset, stride });
+        Ok(())
+    }
+
+    unsafe fn set_indices(&self, buffer: &Self::IndexBuffer) -> Result<()> {
+        self.record(Call::SetIndices(*buffer));
+        Ok(())
+    }
+
+    unsafe fn set_fvf(&self, fvf: u32) -> Result<()> {
+        self.record(Call::SetFVF(fvf));
+        Ok(())
+    }
+
+    unsafe fn draw_indexed_primitive(
+        &self,
+        primitive_type: u32,
+        base_vertex_index: i32,
+        min_vertex_index: u32,
+        num_vertices: u32,
+        start_index: u32,
+        prim_count: u32,
+    ) -> Result<()> {
+        self.record(Call::DrawIndexedPrimitive {
+            primitive_type,
+            base_vertex_index,
+            min_vertex_index,
+            num_vertices,
+            start_index,
+            prim_count,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{hello_window, Fixture};
+    use crate::RendererOptions;
+
+    #[test]
+    fn clones_share_the_log() {
+        let device = RecordingDevice::new();
+        let clone = device.clone();
+        unsafe { clone.set_fvf(D3DFVF_XYZ).unwrap() };
+        assert_eq!(device.calls(), vec![Call::SetFVF(D3DFVF_XYZ)]);
+        device.clear_calls();
+        assert!(clone.calls().is_empty());
+    }
+
+    #[test]
+    fn draws_read_the_written_buffers() {
+        let mut fixture = Fixture::new(RendererOptions::default());
+        let (result, calls) = fixture.render(hello_window);
+        result.unwrap();
+
+        let first_draw =
+            calls.iter().position(|c| matches!(c, Call::DrawIndexedPrimitive { .. })).unwrap();
+        let (mut vb, mut ib) = (None, None);
+        for call in &calls[..first_draw] {
+            match *call {
+                Call::LockVertexBuffer { buffer, .. } => vb = Some(buffer),
+                Call::LockIndexBuffer { buffer, .. } => ib = Some(buffer),
+                Call::UnlockVertexBuffer(buffer) => assert_eq!(vb, Some(buffer)),
+                Call::UnlockIndexBuffer(buffer) => assert_eq!(ib, Some(buffer)),
+                _ => {},
+            }
+        }
+        let (vb, ib) = (vb.unwrap(), ib.unwrap());
+        assert!(calls.contains(&Call::SetIndices(ib)));
+        assert!(calls
+            .iter()
+            .any(|c| matches!(c, Call::SetStreamSource { buffer, .. } if *buffer == vb)));
+
+        let indices = fixture.device.resource_data(ib, 0).unwrap();
+        let indices: Vec<u16> =
+            indices.chunks_exact(2).map(|i| u16::from_le_bytes([i[0], i[1]])).collect();
+        for call in &calls {
+            if let Call::DrawIndexedPrimitive { num_vertices, start_index, prim_count, .. } = *call
+            {
+                let start = start_index as usize;
+                let drawn = &indices[start..start + prim_count as usize * 3];
+                assert!(drawn.iter().all(|&i| u32::from(i) < num_vertices));
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The texture registry of a [`Renderer`].
+//!
+//! [`Renderer`]: crate::Renderer
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use imgui::TextureId;
+
+use crate::{RendererError, Result, SamplerState, FONT_TEX_ID, PLACEHOLDER_TEX_ID};
+
+/// Maps [`TextureId`]s to textures.
+///
+/// Unlike [`imgui::Textures`] the registry refuses to hold a texture under the
+/// ids reserved for the font atlas and the missing texture placeholder, so a
+/// user texture can never shadow them.
+///
+/// The sampler states set through [`Renderer::set_texture_sampler`] are kept
+/// here as well, so removing a texture forgets its sampler state.
+///
+/// [`Renderer::set_texture_sampler`]: crate::Renderer::set_texture_sampler
+pub struct TextureRegistry<T> {
+    textures: HashMap<TextureId, T>,
+    samplers: HashMap<TextureId, SamplerState>,
+    /// Whether each texture is sampled with `D3DSAMP_SRGBTEXTURE`, known
+    /// once it was created by the renderer or first drawn.
+    srgb_read: RefCell<HashMap<TextureId, bool>>,
+    next: usize,
+}
+
+impl<T> TextureRegistry<T> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        TextureRegistry {
+            textures: HashMap::new(),
+            samplers: HashMap::new(),
+            srgb_read: RefCell::default(),
+            next: 0,
+        }
+    }
+
+    /// Whether `texture_id` is reserved by the renderer and cannot hold a
+    /// texture.
+    #[inline]
+    pub fn is_reserved(texture_id: TextureId) -> bool {
+        texture_id.id() == FONT_TEX_ID || texture_id.id() == PLACEHOLDER_TEX_ID
+    }
+
+    /// Inserts a texture under a newly allocated id and returns that id.
+    pub fn insert(&mut self, texture: T) -> TextureId {
+        let mut id = TextureId::from(self.next);
+        while Self::is_reserved(id) || self.textures.contains_key(&id) {
+            id = TextureId::from(id.id().wrapping_add(1));
+        }
+        self.next = id.id().wrapping_add(1);
+        self.textures.insert(id, texture);
+        self.samplers.remove(&id);
+        self.srgb_read.get_mut().remove(&id);
+        id
+    }
+
+    /// Puts a texture under `texture_id`, returning the texture previously
+    /// stored there.
+    ///
+    /// Returns [`RendererError::ReservedTextureId`] if `texture_id` is
+    /// reserved.
+    pub fn replace(&mut self, texture_id: TextureId, texture: T) -> Result<Option<T>> {
+        if Self::is_reserved(texture_id) {
+            return Err(RendererError::ReservedTextureId(texture_id));
+        }
+        self.srgb_read.get_mut().remove(&texture_id);
+        Ok(self.textures.insert(texture_id, texture))
+    }
+
+    /// Removes the texture under `texture_id` along with its sampler state
+    /// and returns it.
+    pub fn remove(&mut self, texture_id: TextureId) -> Option<T> {
+        let texture = self.textures.remove(&texture_id)?;
+        self.samplers.remove(&texture_id);
+        self.srgb_read.get_mut().remove(&texture_id);
+        Some(texture)
+    }
+
+    /// The texture under `texture_id`.
+    pub fn get(&self, texture_id: TextureId) -> Option<&T> {
+        self.textures.get(&texture_id)
+    }
+
+    /// The texture under `texture_id`.
+    pub fn get_mut(&mut self, texture_id: TextureId) -> Option<&mut T> {
+        let texture = self.textures.get_mut(&texture_id)?;
+        // The texture may be swapped for one of another format.
+        self.srgb_read.get_mut().remove(&texture_id);
+        Some(texture)
+    }
+
+    pub(crate) fn set_sampler(&mut self, texture_id: TextureId, sampler: SamplerState) {
+        self.samplers.insert(texture_id, sampler);
+    }
+
+    pub(crate) fn sampler(&self, texture_id: TextureId) -> Option<SamplerState> {
+        self.samplers.get(&texture_id).copied()
+    }
+
+    pub(crate) fn set_srgb_read(&mut self, texture_id: TextureId, srgb_read: bool) {
+        self.srgb_read.get_mut().insert(texture_id, srgb_read);
+    }
+
+    /// Whether the texture under `texture_id` is sampled with
+    /// `D3DSAMP_SRGBTEXTURE`, asking `query` if that is not known yet.
+    pub(crate) fn srgb_read(&self, texture_id: TextureId, query: impl FnOnce() -> bool) -> bool {
+        *self.srgb_read.borrow_mut().entry(texture_id).or_insert_with(query)
+    }
+}
+
+impl<T> Default for TextureRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
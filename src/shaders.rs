@@ -0,0 +1,35 @@
+//! The precompiled shaders of [`Pipeline::Programmable`].
+//!
+//! Both reproduce what the fixed function pipeline draws with: the vertex
+//! shader transforms by the projection in `c0`-`c3` and passes color and
+//! texture coordinate through, the pixel shader modulates the texture with
+//! the vertex color. The bytecode is listed next to the assembly it was
+//! built from.
+//!
+//! [`Pipeline::Programmable`]: crate::Pipeline::Programmable
+
+/// The vertex shader, taking the transposed projection matrix in `c0`-`c3`.
+#[rustfmt::skip]
+pub(crate) const VERTEX_SHADER: &[u32] = &[
+    0xFFFE0200,                                     // vs_2_0
+    0x0200001F, 0x80000000, 0x900F0000,             // dcl_position v0
+    0x0200001F, 0x8000000A, 0x900F0001,             // dcl_color v1
+    0x0200001F, 0x80000005, 0x900F0002,             // dcl_texcoord v2
+    0x03000014, 0xC00F0000, 0x90E40000, 0xA0E40000, // m4x4 oPos, v0, c0
+    0x02000001, 0xD00F0000, 0x90E40001,             // mov oD0, v1
+    0x02000001, 0xE00F0000, 0x90E40002,             // mov oT0, v2
+    0x0000FFFF,                                     // end
+];
+
+/// The pixel shader.
+#[rustfmt::skip]
+pub(crate) const PIXEL_SHADER: &[u32] = &[
+    0xFFFF0200,                                     // ps_2_0
+    0x0200001F, 0x80000000, 0xB0030000,             // dcl t0.xy
+    0x0200001F, 0x80000000, 0x900F0000,             // dcl v0
+    0x0200001F, 0x90000000, 0xA00F0800,             // dcl_2d s0
+    0x03000042, 0x800F0000, 0xB0E40000, 0xA0E40800, // texld r0, t0, s0
+    0x03000005, 0x800F0000, 0x80E40000, 0x90E40000, // mul r0, r0, v0
+    0x02000001, 0x800F0800, 0x80E40000,             // mov oC0, r0
+    0x0000FFFF,                                     // end
+];